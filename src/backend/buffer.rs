@@ -1,17 +1,40 @@
+use super::cursor::{Cursor, Offset};
+use super::format::Format;
+use super::grep::Hit;
+use super::hex::Hex;
 use super::highlight::{Highlighter, TextHighlighting};
+use super::history::{Edit, History, View};
+use super::rope::{Lines, Rope};
+use super::watch::Stamp;
 use std::cell::RefCell;
 use std::fmt;
 use std::iter;
+use std::path::PathBuf;
 use std::str::Bytes;
 use unicode_segmentation::UnicodeSegmentation;
 
 pub struct Buffer {
-    lines: Vec<Line>,
+    lines: Rope,
     pub file_name: Option<String>,
     pub file_type: Option<String>,
     pub dirty: bool,
     pub is_norm: bool,
     pub highlighter: Option<RefCell<Box<dyn Highlighter>>>,
+    /// The encoding, line endings, final newline and BOM to save the buffer with
+    pub format: Format,
+    /// Whether the buffer can't be changed or saved
+    pub read_only: bool,
+    /// The bytes of the buffer while it is being edited in hex, when it doesn't have any lines
+    pub hex: Option<Hex>,
+    /// What the file looked like when it was last read or written
+    pub disk: Option<Stamp>,
+    /// The swap file that the buffer is kept in while it is open, unless another purport has it
+    pub swap: Option<PathBuf>,
+    /// Where the cursor was the last time the buffer was switched away from
+    pub view: View,
+    /// The matches listed by a buffer of project search results, one per line
+    pub hits: Option<Vec<Hit>>,
+    history: History,
 }
 
 impl fmt::Debug for Buffer {
@@ -32,32 +55,43 @@ impl Buffer {
         highlighter: Option<RefCell<Box<dyn Highlighter>>>,
     ) -> Self {
         Buffer {
-            lines,
+            lines: Rope::from(lines),
             is_norm,
             file_name,
             file_type: None, // TODO
             dirty: false,
             highlighter,
+            format: Format::default(),
+            read_only: false,
+            hex: None,
+            disk: None,
+            swap: None,
+            view: View::default(),
+            hits: None,
+            history: History::default(),
         }
     }
     pub fn clear(&mut self) {
-        self.lines = vec![Line::default()];
+        self.lines = Rope::from(vec![Line::default()]);
+        self.history = History::default();
     }
     pub fn insert_char(&mut self, row: usize, col: usize, g: &str) {
         debug_assert!(self.lines() > row);
-        let line = &mut self.lines[row];
-        line.insert_grapheme(col, g);
-        for line in &mut self.lines[row + 1..] {
-            line.offset += 1;
-        }
+        self.record(Edit::Insert {
+            row,
+            col,
+            text: g.to_string(),
+        });
     }
     pub fn delete_char(&mut self, row: usize, col: usize) {
         debug_assert!(col != 0);
-        if let Some(line) = self.lines.get_mut(row) {
-            line.remove(col - 1);
-        }
-        for line in &mut self.lines[row + 1..] {
-            line.offset -= 1;
+        if let Some(text) = self.get(row).and_then(|line| line.grapheme(col - 1)) {
+            let text = text.to_string();
+            self.record(Edit::Delete {
+                row,
+                col: col - 1,
+                text,
+            });
         }
     }
     pub fn get(&self, index: usize) -> Option<&Line> {
@@ -68,26 +102,42 @@ impl Buffer {
         if index == 0 {
             return;
         }
-        let line = &self.lines[index].clone();
-        let prev = self.lines.get_mut(index - 1).unwrap();
-        prev.merge(line);
-        self.lines.remove(index);
-        for line in &mut self.lines[index..] {
-            line.offset -= 1;
+        let col = self.get(index - 1).map_or(0, Line::len);
+        self.record(Edit::Join {
+            row: index - 1,
+            col,
+        });
+    }
+    /// The name of the buffer to show in lists of buffers
+    pub fn name(&self) -> &str {
+        match (&self.file_name, &self.hits) {
+            (Some(file_name), _) => file_name,
+            (None, Some(_)) => "[search results]",
+            (None, None) => "[no name]",
         }
     }
-    pub fn as_slice(&self) -> &[Line] {
-        &self.lines[..]
+
+    /// Whether the buffer is a single empty line that has never been saved anywhere, so that it
+    /// can be reused for the next file that is opened
+    pub fn is_scratch(&self) -> bool {
+        self.file_name.is_none()
+            && !self.dirty
+            && self.lines() <= 1
+            && self.get(0).is_none_or(|line| line.len() == 0)
+    }
+
+    pub fn lines_from(&self, index: usize) -> Lines<'_> {
+        self.lines.iter_from(index)
     }
     pub fn is_empty(&self) -> bool {
-        self.lines() == 0 && self.lines[0] == Line::default()
+        self.lines() == 0
     }
     pub fn lines(&self) -> usize {
-        self.lines.len()
+        self.lines.lines()
     }
     pub fn to_chunk(&self) -> Vec<u8> {
-        let mut buffer = Vec::new();
-        for line in &self.lines {
+        let mut buffer = Vec::with_capacity(self.lines.bytes());
+        for line in self.lines.iter() {
             buffer.reserve(line.len());
             for byte in line.bytes() {
                 buffer.push(byte);
@@ -96,6 +146,15 @@ impl Buffer {
         }
         buffer
     }
+    /// The contents of the buffer to write to its file in its format, or None if it has text
+    /// that its encoding can't represent
+    pub fn to_file(&self) -> Option<Vec<u8>> {
+        if let Some(hex) = &self.hex {
+            return Some(hex.bytes.clone());
+        }
+        self.format
+            .encode(self.lines.iter().map(|line| &line.text[..]))
+    }
     pub fn is_norm(&self) -> bool {
         self.is_norm
     }
@@ -106,59 +165,369 @@ impl Buffer {
     }
 
     // take a byte offset from the start of the buffer and produce a (row, col) position
-    // this is relatively unoptimized: it is currently O(n) but could be O(log n) using binary
-    // search (the lines are guaranteed to be sorted by offset)
     pub fn to_pos(&self, offset: usize) -> (usize, usize) {
-        for (i, line) in self.lines.iter().enumerate().rev() {
-            if line.offset <= offset {
-                return (i, offset - line.offset);
+        self.lines.position(offset)
+    }
+
+    pub fn append_string(&mut self, s: String) {
+        self.record(Edit::InsertLine {
+            row: self.lines(),
+            text: s,
+        });
+    }
+
+    pub fn split_line(&mut self, index: usize, split_col: usize) {
+        debug_assert!(index < self.lines());
+        self.record(Edit::Split {
+            row: index,
+            col: split_col,
+        });
+    }
+
+    /// Insert text that may span several lines at a position in one undoable step, returning
+    /// the position just after the inserted text
+    pub fn insert_text(&mut self, row: usize, col: usize, text: &str) -> (usize, usize) {
+        debug_assert!(self.lines() > row);
+        self.begin_group();
+        let (mut row, mut col) = (row, col);
+        let mut segments = text.split('\n').peekable();
+        while let Some(segment) = segments.next() {
+            if !segment.is_empty() {
+                self.record(Edit::Insert {
+                    row,
+                    col,
+                    text: segment.to_string(),
+                });
+                col += segment.graphemes(true).count();
+            }
+            if segments.peek().is_some() {
+                self.record(Edit::Split { row, col });
+                row += 1;
+                col = 0;
             }
         }
-        if let Some(last) = self.lines.last() {
-            (self.lines.len() - 1, last.offset + last.bytes().len())
-        } else {
-            (0, 0)
+        self.end_group();
+        (row, col)
+    }
+
+    /// The text between two (row, col) positions, with the lines joined by newlines
+    pub fn text_range(&self, start: (usize, usize), end: (usize, usize)) -> String {
+        let mut text = String::new();
+        for (row, line) in self.lines_from(start.0).enumerate() {
+            let row = row + start.0;
+            if row > end.0 {
+                break;
+            }
+            let from = if row == start.0 { start.1 } else { 0 };
+            let to = if row == end.0 { end.1 } else { line.len() };
+            if from < to {
+                text.extend(line.skip(from).graphemes(true).take(to - from));
+            }
+            if row != end.0 {
+                text.push('\n');
+            }
         }
+        text
     }
 
-    pub fn append_string(&mut self, s: String) {
-        if let Some(last) = self.lines.last() {
-            let new_offset = last.offset + last.text.len() + 1; // the +1 is to account for the newline
-            self.lines.push(Line::new(s, new_offset));
-        } else {
-            self.lines.push(Line::new(s, 0));
+    /// Delete the text between two (row, col) positions in one undoable step, returning it
+    pub fn delete_range(&mut self, start: (usize, usize), end: (usize, usize)) -> String {
+        debug_assert!(start <= end);
+        let text = self.text_range(start, end);
+        let len = |buf: &Buffer, row| buf.get(row).map_or(0, Line::len);
+        self.begin_group();
+        if start.0 == end.0 {
+            if start.1 < end.1 {
+                self.record(Edit::Delete {
+                    row: start.0,
+                    col: start.1,
+                    text: text.clone(),
+                });
+            }
+        } else if end.0 < self.lines() {
+            let suffix = self.text_range(start, (start.0, len(self, start.0)));
+            if !suffix.is_empty() {
+                self.record(Edit::Delete {
+                    row: start.0,
+                    col: start.1,
+                    text: suffix,
+                });
+            }
+            for _ in start.0 + 1..end.0 {
+                let line = self.get(start.0 + 1).map(ToString::to_string);
+                self.record(Edit::RemoveLine {
+                    row: start.0 + 1,
+                    text: line.unwrap_or_default(),
+                });
+            }
+            let prefix = self.text_range((start.0 + 1, 0), (start.0 + 1, end.1));
+            if !prefix.is_empty() {
+                self.record(Edit::Delete {
+                    row: start.0 + 1,
+                    col: 0,
+                    text: prefix,
+                });
+            }
+            self.record(Edit::Join {
+                row: start.0,
+                col: start.1,
+            });
         }
+        self.end_group();
+        text
     }
 
-    pub fn split_line(&mut self, index: usize, split_col: usize) {
-        debug_assert!(index < self.lines());
-        let line = &mut self.lines[index];
-        let rest = line.split_at(split_col);
-        self.lines.insert(index + 1, rest);
-        for line in &mut self.lines[index + 1..] {
-            line.offset += 1;
+    /// Find the next occurrence of some text, which may span several lines, starting at a
+    /// position and wrapping around to the start of the buffer, returning where it starts and
+    /// ends
+    pub fn find(
+        &self,
+        needle: &str,
+        from: (usize, usize),
+    ) -> Option<((usize, usize), (usize, usize))> {
+        let rows = self.lines();
+        if needle.is_empty() || rows == 0 {
+            return None;
+        }
+        let parts: Vec<&str> = needle.split('\n').collect();
+        for i in 0..=rows {
+            let row = (from.0 + i) % rows;
+            let col = if i == 0 { from.1 } else { 0 };
+            if let Some(found) = self.find_in_row(row, &parts, col) {
+                // after wrapping all the way around, only matches before the start are new
+                if i < rows || (found.0).1 < from.1 {
+                    return Some(found);
+                }
+            }
+        }
+        None
+    }
+
+    // find a match that starts on the given row at or after col
+    fn find_in_row(
+        &self,
+        row: usize,
+        parts: &[&str],
+        col: usize,
+    ) -> Option<((usize, usize), (usize, usize))> {
+        let line = self.get(row)?;
+        let from = line.to_byte_idx(col);
+        match parts {
+            [] => None,
+            [part] => {
+                let start = line.text[from..].find(part)? + from;
+                let start = line.text[..start].graphemes(true).count();
+                Some(((row, start), (row, start + part.graphemes(true).count())))
+            }
+            [first, middle @ .., last] => {
+                let start = line.text.len().checked_sub(first.len())?;
+                if start < from || !line.text.ends_with(first) {
+                    return None;
+                }
+                for (i, part) in middle.iter().enumerate() {
+                    if self.get(row + i + 1)?.text != *part {
+                        return None;
+                    }
+                }
+                let end_row = row + middle.len() + 1;
+                if !self.get(end_row)?.text.starts_with(last) {
+                    return None;
+                }
+                Some((
+                    (row, line.text[..start].graphemes(true).count()),
+                    (end_row, last.graphemes(true).count()),
+                ))
+            }
+        }
+    }
+
+    pub fn begin_group(&mut self) {
+        self.history.begin_group();
+    }
+
+    pub fn end_group(&mut self) {
+        self.history.end_group();
+    }
+
+    fn record(&mut self, edit: Edit) {
+        self.apply(&edit);
+        self.history.push(edit);
+    }
+
+    // perform an edit on the lines without recording it in the history
+    fn apply(&mut self, edit: &Edit) {
+        match edit {
+            Edit::Insert { row, col, text } => {
+                self.lines.edit(*row, |line| line.insert_grapheme(*col, text));
+            }
+            Edit::Delete { row, col, text } => {
+                let end = col + text.graphemes(true).count();
+                self.lines.edit(*row, |line| line.remove_range(*col, end));
+            }
+            Edit::Split { row, col } => {
+                if let Some(rest) = self.lines.edit(*row, |line| line.split_at(*col)) {
+                    self.lines.insert(row + 1, rest);
+                }
+            }
+            Edit::Join { row, .. } => {
+                if row + 1 < self.lines() {
+                    let below = self.lines.remove(row + 1);
+                    self.lines.edit(*row, |line| line.merge(&below));
+                }
+            }
+            Edit::InsertLine { row, text } => self.lines.insert(*row, Line::new(text.clone())),
+            Edit::RemoveLine { row, .. } => {
+                self.lines.remove(*row);
+            }
+        }
+    }
+
+    /// Tell the history where the editing pane is looking, see `History::set_view`
+    pub fn set_view(&mut self, cursor: Cursor, offset: Offset) {
+        self.history.set_view(View { cursor, offset });
+    }
+
+    /// Undo the most recent step, returning the view from before it was made
+    pub fn undo(&mut self) -> Option<View> {
+        let step = self.history.undo()?;
+        for edit in step.edits.iter().rev() {
+            self.apply(&edit.inverse());
+        }
+        self.dirty = !self.history.is_saved();
+        Some(step.before)
+    }
+
+    /// Redo the most recently undone step, returning the view from after it was made
+    pub fn redo(&mut self) -> Option<View> {
+        let step = self.history.redo()?;
+        for edit in &step.edits {
+            self.apply(edit);
+        }
+        self.dirty = !self.history.is_saved();
+        Some(step.after)
+    }
+
+    /// Move the buffer to any revision in its history, returning the view to restore
+    pub fn goto_revision(&mut self, revision: usize) -> Option<View> {
+        let (edits, view) = self.history.goto(revision)?;
+        for edit in &edits {
+            self.apply(edit);
+        }
+        self.dirty = !self.history.is_saved();
+        Some(view)
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Replace the history of the buffer, which must have been made on the current contents.
+    ///
+    /// Every edit in the history is tried out first, and a history with edits that don't fit the
+    /// lines, such as one from a corrupted undo file, is thrown away instead.
+    pub fn restore_history(&mut self, history: History) {
+        let lines = self.lines.clone();
+        let fits = history.tour().iter().all(|edit| {
+            let fits = self.fits(edit);
+            if fits {
+                self.apply(edit);
+            }
+            fits
+        });
+        self.lines = lines;
+        if fits {
+            self.history = history;
+            self.dirty = !self.history.is_saved();
+        }
+    }
+
+    // whether an edit only touches rows and columns that are in the buffer
+    fn fits(&self, edit: &Edit) -> bool {
+        let len = |row: usize| self.lines.get(row).map(Line::len);
+        match edit {
+            Edit::Insert { row, col, .. } | Edit::Split { row, col } => {
+                len(*row).is_some_and(|len| *col <= len)
+            }
+            Edit::Delete { row, col, text } => {
+                len(*row).is_some_and(|len| col.saturating_add(text.graphemes(true).count()) <= len)
+            }
+            Edit::Join { row, col } => {
+                row.saturating_add(1) < self.lines() && len(*row).is_some_and(|len| *col <= len)
+            }
+            Edit::InsertLine { row, .. } => *row <= self.lines(),
+            Edit::RemoveLine { row, .. } => *row < self.lines(),
         }
     }
 
+    pub fn mark_saved(&mut self) {
+        self.dirty = false;
+        self.history.mark_saved();
+    }
+
+    #[cfg(test)]
     pub fn from_bytes(
         bytes: &[u8],
         file_name: Option<String>,
         highlighter: Option<RefCell<Box<dyn Highlighter>>>,
     ) -> Self {
-        let mut lines = Vec::new();
-        bytes.split(|b| *b == b'\n').fold(0, |offset, line_bytes| {
-            let line = String::from_utf8_lossy(line_bytes).to_string();
-            lines.push(Line::new(line, offset));
-            offset + lines.last().unwrap().text.len() + 1
-        });
-        Buffer {
-            dirty: false,
-            file_name,
-            file_type: None,
-            highlighter,
-            is_norm: true,
-            lines,
+        Buffer::decode(bytes, encoding_rs::WINDOWS_1252, file_name, highlighter)
+    }
+
+    /// Load the contents of a file, reading files that aren't Unicode with the fallback encoding.
+    /// Binary files, which couldn't be saved unchanged as text, are opened in hex instead.
+    pub fn decode(
+        bytes: &[u8],
+        fallback: &'static encoding_rs::Encoding,
+        file_name: Option<String>,
+        highlighter: Option<RefCell<Box<dyn Highlighter>>>,
+    ) -> Self {
+        let mut buffer = Buffer::new(Vec::new(), true, file_name, highlighter);
+        if let Some((format, text)) = Format::decode(bytes, fallback) {
+            buffer.set_text(format, &text);
+        } else {
+            buffer.hex = Some(Hex::new(bytes.to_vec()));
         }
+        buffer
+    }
+
+    // replace the lines of the buffer with some decoded text
+    fn set_text(&mut self, format: Format, text: &str) {
+        let lines = format
+            .split(text)
+            .into_iter()
+            .map(|line| Line::new(line.to_string()))
+            .collect::<Vec<_>>();
+        self.lines = Rope::from(lines);
+        self.format = format;
+    }
+
+    /// Switch to editing the bytes of the buffer in hex, returning false if the text can't be
+    /// encoded. The undo history only covers one way of editing, so it starts again.
+    pub fn start_hex(&mut self) -> bool {
+        if self.hex.is_some() {
+            return true;
+        }
+        let Some(bytes) = self.to_file() else {
+            return false;
+        };
+        self.hex = Some(Hex::new(bytes));
+        self.lines = Rope::from(Vec::new());
+        self.history = History::default();
+        true
+    }
+
+    /// Go back to editing the buffer as text, returning false if the bytes aren't text
+    pub fn stop_hex(&mut self, fallback: &'static encoding_rs::Encoding) -> bool {
+        let Some(hex) = &self.hex else {
+            return true;
+        };
+        let Some((format, text)) = Format::decode(&hex.bytes, fallback) else {
+            return false;
+        };
+        self.set_text(format, &text);
+        self.hex = None;
+        self.history = History::default();
+        true
     }
 }
 
@@ -166,39 +535,34 @@ impl Buffer {
 pub struct Line {
     text: String,
     graphemes: usize,
-    // offset is the starting position of the line from the start of the file.
-    // This includes newlines (which aren't included in Line::text), so calculating this is prone
-    // to off by 1 errors
-    offset: usize,
 }
 
 impl Line {
-    pub fn new(text: String, offset: usize) -> Line {
+    pub fn new(text: String) -> Line {
         let graphemes = text[..].graphemes(true).count();
-        Line {
-            text,
-            graphemes,
-            offset,
-        }
+        Line { text, graphemes }
     }
 
+    /// Insert a string, which may contain several graphemes, before the grapheme at idx
     pub fn insert_grapheme(&mut self, idx: usize, grapheme: &str) {
         debug_assert!(idx <= self.graphemes);
-        self.graphemes += 1;
-        if idx == self.graphemes - 1 {
-            self.text.push_str(grapheme);
-        } else {
-            let mut new_row = String::with_capacity(self.text.len() + grapheme.len());
-            new_row.extend(self.text[..].graphemes(true).take(idx));
-            new_row.extend(grapheme.graphemes(true));
-            new_row.extend(self.text[..].graphemes(true).skip(idx));
-            self.text = new_row;
-        }
+        let g_idx = self.to_byte_idx(idx);
+        self.text.insert_str(g_idx, grapheme);
+        self.graphemes = self.text[..].graphemes(true).count();
+    }
+    /// Remove the graphemes from start up to end, returning them
+    pub fn remove_range(&mut self, start: usize, end: usize) -> String {
+        debug_assert!(start <= end && end <= self.graphemes);
+        let start = self.to_byte_idx(start);
+        let end = self.to_byte_idx(end);
+        let removed = self.text.drain(start..end).collect();
+        self.graphemes = self.text[..].graphemes(true).count();
+        removed
     }
     pub fn split_at(&mut self, idx: usize) -> Line {
         debug_assert!(idx <= self.graphemes);
         if idx == self.graphemes {
-            Line::new(String::new(), self.offset + self.len())
+            Line::default()
         } else {
             let rest = self.text[..].graphemes(true).skip(idx).collect();
             let g_idx = self.to_byte_idx(idx);
@@ -208,20 +572,9 @@ impl Line {
             Line {
                 text: rest,
                 graphemes: new_len,
-                offset: g_idx,
             }
         }
     }
-    pub fn remove(&mut self, idx: usize) {
-        debug_assert!(idx < self.graphemes);
-        debug_assert!(self.graphemes > 0);
-        let rest: String = self.text[..].graphemes(true).skip(idx + 1).collect();
-        let g_idx = self.to_byte_idx(idx);
-        self.text.truncate(g_idx);
-        self.text.extend(rest[..].graphemes(true));
-        self.graphemes -= 1;
-    }
-
     fn to_byte_idx(&self, idx: usize) -> usize {
         self.text[..]
             .grapheme_indices(true)
@@ -229,7 +582,11 @@ impl Line {
             .fold(None, |acc, (i, (g, _))| {
                 acc.or(if i == idx { Some(g) } else { None })
             })
-            .unwrap_or(0)
+            .unwrap_or(self.text.len())
+    }
+
+    pub fn grapheme(&self, idx: usize) -> Option<&str> {
+        self.text[..].graphemes(true).nth(idx)
     }
 
     pub fn skip(&self, idx: usize) -> &str {
@@ -250,6 +607,10 @@ impl Line {
     pub fn bytes(&self) -> Bytes {
         self.text.bytes()
     }
+
+    pub fn byte_len(&self) -> usize {
+        self.text.len()
+    }
 }
 
 impl<'a> iter::Extend<&'a str> for Line {
@@ -272,6 +633,7 @@ impl fmt::Display for Line {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::backend::testing::buffer;
 
     #[test]
     fn insert() {
@@ -291,30 +653,154 @@ mod test {
 
     #[test]
     fn split() {
-        let mut l = Line::new(String::from("abcdefg"), 0);
+        let mut l = Line::new(String::from("abcdefg"));
         let rest = l.split_at(4);
 
-        assert_eq!(l, Line::new(String::from("abcd"), 0));
-        assert_eq!(rest, Line::new(String::from("efg"), 0));
+        assert_eq!(l, Line::new(String::from("abcd")));
+        assert_eq!(rest, Line::new(String::from("efg")));
 
-        let mut l = Line::new(String::from("\u{2606}bcd\u{2606}fg"), 0);
+        let mut l = Line::new(String::from("\u{2606}bcd\u{2606}fg"));
         let rest = l.split_at(4);
-        assert_eq!(l, Line::new(String::from("\u{2606}bcd"), 0));
-        assert_eq!(rest, Line::new(String::from("\u{2606}fg"), 0));
+        assert_eq!(l, Line::new(String::from("\u{2606}bcd")));
+        assert_eq!(rest, Line::new(String::from("\u{2606}fg")));
     }
 
     #[test]
     fn remove() {
-        let mut l = Line::new(String::from("abcdefg"), 0);
-        l.remove(3);
-        assert_eq!(l, Line::new(String::from("abcefg"), 0));
+        let mut l = Line::new(String::from("abcdefg"));
+        l.remove_range(3, 4);
+        assert_eq!(l, Line::new(String::from("abcefg")));
+    }
+
+    #[test]
+    fn undo_groups_typing() {
+        let mut b = buffer("");
+        for (col, g) in ["a", "b", "c"].iter().enumerate() {
+            b.insert_char(0, col, g);
+        }
+        b.split_line(0, 3);
+        b.insert_char(1, 0, "d");
+        assert_eq!(b.to_chunk(), b"abc\nd\n");
+        b.undo();
+        assert_eq!(b.to_chunk(), b"abc\n\n");
+        b.undo();
+        assert_eq!(b.to_chunk(), b"abc\n");
+        b.undo();
+        assert_eq!(b.to_chunk(), b"\n");
+        assert!(b.undo().is_none());
+        b.redo();
+        b.redo();
+        assert_eq!(b.to_chunk(), b"abc\n\n");
+    }
+
+    #[test]
+    fn undo_deletes() {
+        let mut b = buffer("ab\ncd");
+        b.delete_char(1, 2);
+        b.delete_char(1, 1);
+        b.merge_with_above(1);
+        b.append_string(String::from("ef"));
+        assert_eq!(b.to_chunk(), b"ab\nef\n");
+        b.undo();
+        b.undo();
+        assert_eq!(b.to_chunk(), b"ab\n\n");
+        b.undo();
+        assert_eq!(b.to_chunk(), b"ab\ncd\n");
+    }
+
+    #[test]
+    fn undo_tree_keeps_branches() {
+        let mut b = buffer("");
+        b.insert_char(0, 0, "a");
+        let first = b.history().current();
+        b.split_line(0, 1);
+        b.undo();
+        b.insert_char(0, 1, "b");
+        assert_eq!(b.to_chunk(), b"ab\n");
+        b.goto_revision(first + 1);
+        assert_eq!(b.to_chunk(), b"a\n\n");
+        b.goto_revision(0);
+        assert_eq!(b.to_chunk(), b"\n");
+        b.redo();
+        b.redo();
+        assert_eq!(b.to_chunk(), b"a\n\n");
+        assert_eq!(b.history().render().len(), 4);
+    }
+
+    #[test]
+    fn history_round_trips() {
+        let mut b = buffer("ab");
+        b.insert_char(0, 2, "c");
+        b.split_line(0, 1);
+        b.undo();
+        b.delete_char(0, 3);
+        b.mark_saved();
+
+        let mut loaded = buffer("ab");
+        loaded.restore_history(b.history().to_toml().parse().unwrap());
+        assert!(!loaded.dirty);
+        assert_eq!(loaded.history().render().len(), 4);
+        loaded.undo();
+        assert_eq!(loaded.to_chunk(), b"abc\n");
+        loaded.goto_revision(2);
+        assert_eq!(loaded.to_chunk(), b"a\nbc\n");
+    }
+
+    #[test]
+    fn history_that_doesnt_fit_is_discarded() {
+        let mut b = buffer("abc\ndef");
+        b.split_line(1, 2);
+        b.undo();
+        b.delete_char(0, 3);
+        let history = b.history().to_toml();
+
+        let mut loaded = buffer("ab\ndef");
+        loaded.restore_history(history.parse().unwrap());
+        assert_eq!(loaded.history().render().len(), 3);
+
+        let mut corrupt = buffer("ab");
+        corrupt.restore_history(history.parse().unwrap());
+        assert_eq!(corrupt.history().render().len(), 1);
+        assert!(!corrupt.dirty);
+        corrupt.undo();
+        assert_eq!(corrupt.to_chunk(), b"ab\n");
+    }
+
+    #[test]
+    fn bulk_insert_and_delete() {
+        let mut b = buffer("abc\ndef");
+        assert_eq!(b.insert_text(0, 1, "12\n\n34"), (2, 2));
+        assert_eq!(b.to_chunk(), b"a12\n\n34bc\ndef\n");
+        assert_eq!(b.text_range((0, 2), (3, 1)), "2\n\n34bc\nd");
+        assert_eq!(b.delete_range((0, 2), (3, 1)), "2\n\n34bc\nd");
+        assert_eq!(b.to_chunk(), b"a1ef\n");
+        b.undo();
+        assert_eq!(b.to_chunk(), b"a12\n\n34bc\ndef\n");
+        b.undo();
+        assert_eq!(b.to_chunk(), b"abc\ndef\n");
+    }
+
+    #[test]
+    fn undo_resets_dirty() {
+        let mut b = buffer("");
+        b.insert_char(0, 0, "a");
+        b.dirty = true;
+        b.mark_saved();
+        b.insert_char(0, 1, "b");
+        b.dirty = true;
+        b.undo();
+        assert!(!b.dirty);
+        b.undo();
+        assert!(b.dirty);
+        b.redo();
+        assert!(!b.dirty);
     }
 
     #[test]
     fn to_byte_index() {
-        let l = Line::new(String::from("abc"), 0);
+        let l = Line::new(String::from("abc"));
         assert_eq!(l.to_byte_idx(2), 2);
-        let l = Line::new(String::from("\u{2606}bc"), 0);
+        let l = Line::new(String::from("\u{2606}bc"));
         assert_eq!(l.to_byte_idx(2), 4);
     }
 }
@@ -0,0 +1,258 @@
+use std::fmt;
+use std::str;
+
+/// Something the editor can do, which keys are bound to by name in the keymap
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Command {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    ToggleSelection,
+    Copy,
+    Cut,
+    Paste,
+    InsertText,
+    Backspace,
+    ChooseRegister,
+    AddCursorBelow,
+    AddCursorAtNextMatch,
+    Cancel,
+    Undo,
+    Redo,
+    BrowseHistory,
+    Search,
+    SearchNext,
+    SearchPrevious,
+    Replace,
+    OpenFile,
+    Save,
+    Quit,
+    CloseBuffer,
+    ListBuffers,
+    NextBuffer,
+    PreviousBuffer,
+    FindFile,
+    SearchProject,
+    ReplaceProject,
+    ToggleExplorer,
+    ConvertFormat,
+    SetFallbackEncoding,
+    SetBackup,
+    ToggleHex,
+    ToggleHexInsert,
+    NewTab,
+    CloseTab,
+    RenameTab,
+    NextTab,
+    PreviousTab,
+    MoveTabLeft,
+    MoveTabRight,
+    GotoTab,
+    SplitBelow,
+    SplitRight,
+    ClosePane,
+    FocusLeft,
+    FocusDown,
+    FocusUp,
+    FocusRight,
+    GrowHeight,
+    ShrinkHeight,
+    GrowWidth,
+    ShrinkWidth,
+    NormalMode,
+    InsertMode,
+    Palette,
+}
+
+impl Command {
+    /// Every command there is
+    pub const ALL: &'static [Command] = &[
+        Command::MoveUp,
+        Command::MoveDown,
+        Command::MoveLeft,
+        Command::MoveRight,
+        Command::SelectUp,
+        Command::SelectDown,
+        Command::SelectLeft,
+        Command::SelectRight,
+        Command::ToggleSelection,
+        Command::Copy,
+        Command::Cut,
+        Command::Paste,
+        Command::InsertText,
+        Command::Backspace,
+        Command::ChooseRegister,
+        Command::AddCursorBelow,
+        Command::AddCursorAtNextMatch,
+        Command::Cancel,
+        Command::Undo,
+        Command::Redo,
+        Command::BrowseHistory,
+        Command::Search,
+        Command::SearchNext,
+        Command::SearchPrevious,
+        Command::Replace,
+        Command::OpenFile,
+        Command::Save,
+        Command::Quit,
+        Command::CloseBuffer,
+        Command::ListBuffers,
+        Command::NextBuffer,
+        Command::PreviousBuffer,
+        Command::FindFile,
+        Command::SearchProject,
+        Command::ReplaceProject,
+        Command::ToggleExplorer,
+        Command::ConvertFormat,
+        Command::SetFallbackEncoding,
+        Command::SetBackup,
+        Command::ToggleHex,
+        Command::ToggleHexInsert,
+        Command::NewTab,
+        Command::CloseTab,
+        Command::RenameTab,
+        Command::NextTab,
+        Command::PreviousTab,
+        Command::MoveTabLeft,
+        Command::MoveTabRight,
+        Command::GotoTab,
+        Command::SplitBelow,
+        Command::SplitRight,
+        Command::ClosePane,
+        Command::FocusLeft,
+        Command::FocusDown,
+        Command::FocusUp,
+        Command::FocusRight,
+        Command::GrowHeight,
+        Command::ShrinkHeight,
+        Command::GrowWidth,
+        Command::ShrinkWidth,
+        Command::NormalMode,
+        Command::InsertMode,
+        Command::Palette,
+    ];
+
+    /// The name the command goes by in keymaps
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::MoveUp => "move-up",
+            Command::MoveDown => "move-down",
+            Command::MoveLeft => "move-left",
+            Command::MoveRight => "move-right",
+            Command::SelectUp => "select-up",
+            Command::SelectDown => "select-down",
+            Command::SelectLeft => "select-left",
+            Command::SelectRight => "select-right",
+            Command::ToggleSelection => "toggle-selection",
+            Command::Copy => "copy",
+            Command::Cut => "cut",
+            Command::Paste => "paste",
+            Command::InsertText => "insert-text",
+            Command::Backspace => "backspace",
+            Command::ChooseRegister => "choose-register",
+            Command::AddCursorBelow => "add-cursor-below",
+            Command::AddCursorAtNextMatch => "add-cursor-at-next-match",
+            Command::Cancel => "cancel",
+            Command::Undo => "undo",
+            Command::Redo => "redo",
+            Command::BrowseHistory => "browse-history",
+            Command::Search => "search",
+            Command::SearchNext => "search-next",
+            Command::SearchPrevious => "search-previous",
+            Command::Replace => "replace",
+            Command::OpenFile => "open-file",
+            Command::Save => "save",
+            Command::Quit => "quit",
+            Command::CloseBuffer => "close-buffer",
+            Command::ListBuffers => "list-buffers",
+            Command::NextBuffer => "next-buffer",
+            Command::PreviousBuffer => "previous-buffer",
+            Command::FindFile => "find-file",
+            Command::SearchProject => "search-project",
+            Command::ReplaceProject => "replace-project",
+            Command::ToggleExplorer => "toggle-explorer",
+            Command::ConvertFormat => "convert-format",
+            Command::SetFallbackEncoding => "set-fallback-encoding",
+            Command::SetBackup => "set-backup",
+            Command::ToggleHex => "toggle-hex",
+            Command::ToggleHexInsert => "toggle-hex-insert",
+            Command::NewTab => "new-tab",
+            Command::CloseTab => "close-tab",
+            Command::RenameTab => "rename-tab",
+            Command::NextTab => "next-tab",
+            Command::PreviousTab => "previous-tab",
+            Command::MoveTabLeft => "move-tab-left",
+            Command::MoveTabRight => "move-tab-right",
+            Command::GotoTab => "goto-tab",
+            Command::SplitBelow => "split-below",
+            Command::SplitRight => "split-right",
+            Command::ClosePane => "close-pane",
+            Command::FocusLeft => "focus-left",
+            Command::FocusDown => "focus-down",
+            Command::FocusUp => "focus-up",
+            Command::FocusRight => "focus-right",
+            Command::GrowHeight => "grow-height",
+            Command::ShrinkHeight => "shrink-height",
+            Command::GrowWidth => "grow-width",
+            Command::ShrinkWidth => "shrink-width",
+            Command::NormalMode => "normal-mode",
+            Command::InsertMode => "insert-mode",
+            Command::Palette => "command-palette",
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug)]
+pub struct IsNotCommand(String);
+
+impl fmt::Display for IsNotCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "there is no command called {}", self.0)
+    }
+}
+
+impl str::FromStr for Command {
+    type Err = IsNotCommand;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Command::ALL
+            .iter()
+            .copied()
+            .find(|command| command.name() == s)
+            .ok_or_else(|| IsNotCommand(s.to_string()))
+    }
+}
+
+/// A command along with what it works on, for commands that would otherwise ask for it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Binding {
+    pub command: Command,
+    pub arg: Option<String>,
+}
+
+impl str::FromStr for Binding {
+    type Err = IsNotCommand;
+
+    /// Parse a command name, followed by its argument after a space if it has one
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = match s.trim().split_once(' ') {
+            Some((name, arg)) => (name, Some(arg.trim().to_string())),
+            None => (s.trim(), None),
+        };
+        Ok(Binding {
+            command: name.parse()?,
+            arg,
+        })
+    }
+}
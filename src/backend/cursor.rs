@@ -28,13 +28,50 @@ impl Offset {
     }
 }
 
-#[derive(Copy, Clone, Debug, Default)]
+// the fields are in (row, col) order so that the derived ordering is the order in the buffer
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Cursor {
     pub row: usize,
     pub col: usize,
 }
 
+/// A cursor along with the other end of its selection, if it has one
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Selection {
+    pub cursor: Cursor,
+    pub anchor: Option<Cursor>,
+}
+
+impl Selection {
+    /// The start and end of the selected text, which are both the cursor if nothing is selected
+    pub fn bounds(&self) -> (Cursor, Cursor) {
+        match self.anchor {
+            Some(anchor) => (anchor.min(self.cursor), anchor.max(self.cursor)),
+            None => (self.cursor, self.cursor),
+        }
+    }
+}
+
 impl Cursor {
+    /// Where this position ends up after the text up to `old_end` is replaced by text that ends
+    /// at `new_end`, for positions after the replaced text
+    pub fn shift(self, old_end: Cursor, new_end: Cursor) -> Cursor {
+        if self < old_end {
+            // the position was inside the replaced text
+            new_end
+        } else if self.row == old_end.row {
+            Cursor {
+                row: new_end.row,
+                col: self.col - old_end.col + new_end.col,
+            }
+        } else {
+            Cursor {
+                row: self.row - old_end.row + new_end.row,
+                col: self.col,
+            }
+        }
+    }
+
     pub fn move_left_right(
         &mut self,
         buffer: &Buffer,
@@ -0,0 +1,19 @@
+use sha2::{Digest, Sha256};
+use std::env;
+use std::path::PathBuf;
+
+/// The purport directory inside an XDG base directory, which is named by an environment variable
+/// or else found at a fallback path relative to the home directory
+pub fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let mut dir = env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))?;
+    dir.push("purport");
+    Some(dir)
+}
+
+/// The hex SHA-256 of some bytes, which files kept for other files are named and checked by
+pub fn hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
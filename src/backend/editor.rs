@@ -1,17 +1,38 @@
 use super::buffer::{Buffer, Line};
+use super::command::{Binding, Command};
 use super::cursor::{Cursor, Offset};
+use super::explorer::Explorer;
+use super::format::Encoding;
+use super::fuzzy;
+use super::grep::{self, Hunk};
 use super::highlight::Theme;
-use super::pane::{Char, Pane};
+use super::history::View;
+use super::ignore;
+use super::keymap::{self, Keymap, Lookup};
+use super::layout::{Direction, Layout, Rect, Segment, Towards};
+use super::normal::{self, Insert, Operator, Pending, Range};
+use super::pane::{Char, Pane, RowIter};
 use super::prompt::Prompt;
-use crate::frontend::ui::{self, EscapeSeq, Event, UI};
-use std::path::PathBuf;
+use super::register::Registers;
+use super::save::{self, Backup};
+use super::search::{self, Search};
+use super::swap;
+use super::tab::{self, Tab};
+use super::undofile;
+use super::watch::{self, Stamp, Watcher};
+use crate::frontend::ui::{self, CursorShape, EscapeSeq, Event, UI};
+use encoding_rs::WINDOWS_1252;
+use std::path::{Path, PathBuf};
 
 use super::language::Languages;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs;
 use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
 
 #[cfg(unix)]
 static C_COMPILER: &str = "gcc";
@@ -20,29 +41,107 @@ static CPP_COMPILER: &str = "g++";
 #[cfg(unix)]
 static TARGET_DIR: &str = "./target/temp/";
 
+// how long to wait for a key before checking whether open files have changed
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+// the most matches that are listed when finding files
+const MAX_MATCHES: usize = 200;
+// the most columns the file explorer takes up
+const EXPLORER_WIDTH: usize = 30;
+// the background of the open tab in the tab bar
+const OPEN_TAB_COLOUR: ui::Colour = ui::Colour::Blue;
+// how many percent of a split resizing a pane moves it by
+const RESIZE_STEP: isize = 5;
+// how often the swap files of changed buffers are written
+const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+
 #[cfg(windows)]
 static DEFAULT_SYSTEM_DATA: GlobalSystemData = panic!("i am not familiar with windows and so don't know what sensible system defaults would be, if you are looking for windows support, please submit a pull request");
 
 #[derive(Debug)]
 pub struct Editor<U: UI> {
     buffers: Vec<Buffer>,
+    // the pane with the focus, which is kept out of the layout so that it can be used alongside
+    // the buffers
     pane: Pane,
+    layout: Layout,
+    // every tab page, where the one that is open only keeps its name while the editor has its panes
+    tabs: Vec<Tab>,
+    // which tab is open
+    tab: usize,
+    // the file explorer side pane, if it is open
+    explorer: Option<Explorer>,
+    // whether keys go to the file explorer rather than the focused pane
+    explorer_focused: bool,
+    // the rows that the tab bar and the layout get, which are fewer while there is a popup
+    height: usize,
+    // a pane drawn between the main pane and the prompt, which has the cursor while it is open
+    popup: Option<Pane>,
+    registers: Registers,
+    search: Search,
+    // the encoding files that aren't Unicode are read with
+    fallback: &'static encoding_rs::Encoding,
+    // the register chosen for the next copy, cut or paste
+    register: Option<char>,
+    // what is kept of the old contents of files when they are saved over
+    backup: Backup,
+    // notices when open files are changed by something else, if inotify is working
+    watcher: Option<Watcher>,
+    // when swap files were last written, if there have been changes since
+    swap_pending: Option<Instant>,
     mode: Mode,
+    // what has been typed so far of a Normal mode command
+    pending: Pending,
+    // the keys of the Normal mode command being typed, which go on to include what is typed in
+    // Insert mode while a change is being recorded
+    keys: Vec<Event>,
+    // whether the keys typed in Insert mode are part of a change that `.` will repeat
+    recording: bool,
+    // the keys of the last change, which `.` plays back
+    last_change: Vec<Event>,
+    // the keys typed so far of a chord in the keymap
+    chord: Vec<Event>,
+    keymap: Keymap,
+    // whether `.` is playing keys back, so that they aren't recorded over the change
+    replaying: bool,
+    // where the cursor was when Visual mode started
+    visual_start: Cursor,
     prompt: Prompt,
     ui: U,
     theme: Theme,
     extensions: Languages,
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
 pub enum Mode {
     Insert = 0,
+    Normal,
+    Visual,
+    VisualLine,
+}
+
+impl Mode {
+    /// The name of the mode in the status bar
+    pub fn indicator(self) -> &'static str {
+        match self {
+            Mode::Insert => "INSERT",
+            Mode::Normal => "NORMAL",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "V-LINE",
+        }
+    }
+
+    pub fn cursor_shape(self) -> CursorShape {
+        match self {
+            Mode::Insert => CursorShape::Bar,
+            Mode::Normal => CursorShape::Block,
+            Mode::Visual | Mode::VisualLine => CursorShape::Underline,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Error {
     BufferClosedPrematurely(usize),
-    IO(io::Error),
     UI(ui::Error),
 }
 
@@ -53,6 +152,26 @@ impl<U: UI> Editor<U> {
             Buffer::new(vec![Line::default()], true, None, None),
         ];
         let prompt = Prompt::new(ui.width(), 0, &mut buffers, "")?;
+        // a broken keymap falls back on the default one, saying why once the editor is up
+        let (keymap, message) = match Keymap::load() {
+            Ok(keymap) => (keymap, None),
+            Err(e) => (
+                Keymap::default(),
+                Some(format!("failed to load the keymap, using the default: {e}")),
+            ),
+        };
+        // without a watcher, changes to open files are still caught before saving over them
+        let (watcher, message) = match Watcher::new() {
+            Ok(watcher) => (Some(watcher), message),
+            Err(e) => {
+                let failed = format!("failed to start watching files: {e}");
+                let message = match message {
+                    Some(message) => format!("{message}; {failed}"),
+                    None => failed,
+                };
+                (None, Some(message))
+            }
+        };
 
         Ok(Editor {
             buffers,
@@ -60,10 +179,32 @@ impl<U: UI> Editor<U> {
                 width: ui.width(),
                 height: ui.height() - 1,
                 buffer_id: 1,
-                offset: Offset::default(),
-                cursor: Cursor::default(),
+                message,
+                ..Pane::default()
             },
-            mode: Mode::Insert,
+            layout: Layout::default(),
+            tabs: vec![Tab::default()],
+            tab: 0,
+            explorer: None,
+            explorer_focused: false,
+            height: ui.height() - 1,
+            popup: None,
+            registers: Registers::default(),
+            search: Search::default(),
+            fallback: WINDOWS_1252,
+            register: None,
+            backup: Backup::default(),
+            watcher,
+            swap_pending: None,
+            mode: keymap.start,
+            pending: Pending::default(),
+            keys: Vec::new(),
+            recording: false,
+            last_change: Vec::new(),
+            chord: Vec::new(),
+            keymap,
+            replaying: false,
+            visual_start: Cursor::default(),
             prompt,
             ui,
             theme: Theme::default(),
@@ -72,20 +213,38 @@ impl<U: UI> Editor<U> {
     }
 
     pub fn load_into(&mut self, buffer_id: usize, file_name: Option<String>) -> Option<()> {
+        if let Some(path) = self.buffers.get_mut(buffer_id)?.swap.take() {
+            self.remove_swap(&path);
+        }
         let buffer = self.buffers.get_mut(buffer_id)?;
-        if let Some(bytes) = file_name.clone().and_then(|fp| fs::read(&fp).ok()) {
-            let h = if let Some(fp) = &file_name {
-                match self.extensions.get(fp) {
-                    Ok(res) => Some(res),
-                    Err(e) => {
-                        eprintln!("{:?}", e);
-                        None
-                    }
+        if let Some((fp, bytes)) = file_name
+            .clone()
+            .and_then(|fp| fs::read(&fp).ok().map(|bytes| (fp, bytes)))
+        {
+            let h = match self.extensions.get(&fp) {
+                Ok(res) => Some(RefCell::new(res)),
+                Err(e) => {
+                    eprintln!("{:?}", e);
+                    None
                 }
+            };
+            *buffer = Buffer::decode(&bytes, self.fallback, file_name, h);
+            buffer.disk = Some(Stamp::new(&fp, &bytes));
+            if let Some(watcher) = &mut self.watcher {
+                if let Err(e) = watcher.watch(&fp) {
+                    self.pane.message = Some(format!("failed to watch {fp} for changes: {e}"));
+                }
+            }
+            if buffer.hex.is_some() {
+                self.pane.message = Some(format!("{fp} looks like a binary file, opened in hex"));
             } else {
-                None
-            }?;
-            *buffer = Buffer::from_bytes(&bytes, file_name, Some(RefCell::new(h)));
+                match undofile::load(&fp, &bytes) {
+                    Ok(Some(history)) => buffer.restore_history(history),
+                    Ok(None) => {}
+                    Err(e) => self.pane.message = Some(e.to_string()),
+                }
+            }
+            self.open_swap(buffer_id, &fp, &bytes);
         } else {
             buffer.clear();
             buffer.file_name = None;
@@ -100,11 +259,50 @@ impl<U: UI> Editor<U> {
             .buffers
             .get_mut(buffer_id)
             .ok_or(Error::BufferClosedPrematurely(buffer_id))?;
-        if let Some(fp) = &buffer.file_name {
-            fs::write(fp, buffer.to_chunk()).map_err(Error::IO)?;
-            buffer.dirty = false;
+        if buffer.read_only {
+            self.pane.message = Some(String::from("the buffer is read only"));
+        } else if let Some(fp) = buffer.file_name.clone() {
+            let Some(chunk) = buffer.to_file() else {
+                self.pane.message = Some(format!(
+                    "can't save: the buffer has characters that {} can't encode",
+                    buffer.format.encoding
+                ));
+                return Ok(());
+            };
+            if buffer.disk.as_ref().is_some_and(|disk| disk.changed(&fp)) {
+                let question =
+                    format!("{fp} has changed since it was opened, save over it anyway (y/n): ");
+                if !self.confirm(&question)? {
+                    return Ok(());
+                }
+                buffer = self
+                    .buffers
+                    .get_mut(buffer_id)
+                    .ok_or(Error::BufferClosedPrematurely(buffer_id))?;
+            }
+            // the file on disk is left as it was, so the buffer keeps its changes to try again
+            if let Err(e) = save::write(&fp, &chunk, self.backup) {
+                self.pane.message = Some(format!("failed to save {fp}: {e}"));
+                return Ok(());
+            }
+            buffer.disk = Some(Stamp::new(&fp, &chunk));
+            buffer.mark_saved();
+            if let Some(path) = &buffer.swap {
+                if let Err(e) = swap::write(path, &chunk) {
+                    self.pane.message = Some(format!("failed to write the swap file of {fp}: {e}"));
+                }
+            }
+            if buffer.hex.is_some() {
+                // there's no undo history for edits in hex
+            } else if let Err(e) = undofile::save(&fp, &chunk, buffer.history()) {
+                self.pane.message = Some(format!("failed to save the undo history of {fp}: {e}"));
+            }
         } else {
-            let new_name = Some(self.prompt("Enter the file name: ")?);
+            let new_name = self.prompt("Enter the file name: ")?;
+            if new_name.is_empty() {
+                return Ok(());
+            }
+            let new_name = Some(new_name);
             let mut buffer = self
                 .buffers
                 .get_mut(buffer_id)
@@ -115,6 +313,268 @@ impl<U: UI> Editor<U> {
         Ok(())
     }
 
+    /// Open a file in a new buffer and switch to it, or just switch to it if it is already open
+    pub fn open_file(&mut self, file_name: String) -> Result<(), Error> {
+        if Path::new(&file_name).is_dir() {
+            self.open_explorer(PathBuf::from(file_name));
+            return Ok(());
+        }
+        if let Some(buffer_id) = self.find_buffer(&file_name) {
+            return self.switch_to(buffer_id);
+        }
+        let scratch = self
+            .buffers
+            .get(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?
+            .is_scratch();
+        let buffer_id = if scratch {
+            self.pane.buffer_id
+        } else {
+            self.buffers
+                .push(Buffer::new(vec![Line::default()], true, None, None));
+            self.buffers.len() - 1
+        };
+        self.load_into(buffer_id, Some(file_name.clone()));
+        let buffer = &mut self.buffers[buffer_id];
+        // files that don't exist yet get made when they are saved
+        buffer.file_name.get_or_insert(file_name);
+        if scratch {
+            self.show(buffer_id)
+        } else {
+            self.switch_to(buffer_id)
+        }
+    }
+
+    // the buffer that a file is open in, if it is open
+    fn find_buffer(&self, file_name: &str) -> Option<usize> {
+        let resolved = save::resolve(Path::new(file_name))
+            .and_then(fs::canonicalize)
+            .ok();
+        (1..self.buffers.len()).find(|id| {
+            self.buffers[*id]
+                .file_name
+                .as_deref()
+                .is_some_and(|fp| match &resolved {
+                    Some(resolved) => watch::is_file(fp, resolved),
+                    None => fp == file_name,
+                })
+        })
+    }
+
+    /// Show another buffer in the pane, remembering where the pane was in the current one
+    pub fn switch_to(&mut self, buffer_id: usize) -> Result<(), Error> {
+        if buffer_id == self.pane.buffer_id {
+            return Ok(());
+        }
+        self.buffers
+            .get_mut(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?
+            .view = View {
+            cursor: self.pane.cursor,
+            offset: self.pane.offset,
+        };
+        self.show(buffer_id)
+    }
+
+    // put a buffer in the pane, where it was last looked at
+    fn show(&mut self, buffer_id: usize) -> Result<(), Error> {
+        let view = self
+            .buffers
+            .get(buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(buffer_id))?
+            .view;
+        self.pane.buffer_id = buffer_id;
+        self.pane.cursor = view.cursor;
+        self.pane.offset = view.offset;
+        self.pane.clamp_cursor(&self.buffers)
+    }
+
+    /// Switch to the next or the previous open buffer, wrapping around at the ends
+    pub fn cycle_buffers(&mut self, forward: bool) -> Result<(), Error> {
+        let open = self.buffers.len() - 1;
+        let idx = self.pane.buffer_id - 1;
+        let next = if forward {
+            (idx + 1) % open
+        } else {
+            (idx + open - 1) % open
+        };
+        self.switch_to(next + 1)
+    }
+
+    /// Close a buffer, checking first if it has unsaved changes. The last buffer to be closed is
+    /// replaced by an empty one.
+    pub fn close_buffer(&mut self, buffer_id: usize) -> Result<(), Error> {
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(buffer_id))?;
+        if buffer.dirty {
+            let question = format!(
+                "{} has unsaved changes, close it anyway (y/n): ",
+                buffer.name()
+            );
+            if !self.confirm(&question)? {
+                return Ok(());
+            }
+        }
+        let buffer = self.buffers.remove(buffer_id);
+        if let Some(path) = buffer.swap {
+            self.remove_swap(&path);
+        }
+        if self.buffers.len() == 1 {
+            self.buffers
+                .push(Buffer::new(vec![Line::default()], true, None, None));
+        }
+        let next = buffer_id.min(self.buffers.len() - 1);
+        self.for_other_panes(|pane, buffers| {
+            if pane.buffer_id == buffer_id {
+                pane.buffer_id = next;
+                pane.cursor = buffers[next].view.cursor;
+                pane.keep_in_buffer(buffers)?;
+            } else if pane.buffer_id > buffer_id {
+                pane.buffer_id -= 1;
+            }
+            Ok(())
+        })?;
+        if self.pane.buffer_id == buffer_id {
+            self.show(next)
+        } else {
+            if self.pane.buffer_id > buffer_id {
+                self.pane.buffer_id -= 1;
+            }
+            Ok(())
+        }
+    }
+
+    // open a popup listing every open buffer, with a + by the ones with unsaved changes: enter
+    // switches to the selected buffer, d closes it and q goes back
+    pub fn list_buffers(&mut self) -> Result<(), Error> {
+        let render = |buffers: &[Buffer]| -> Vec<Line> {
+            buffers[1..]
+                .iter()
+                .map(|buffer| {
+                    let dirty = if buffer.dirty { '+' } else { ' ' };
+                    Line::new(format!("{dirty} {}", buffer.name()))
+                })
+                .collect()
+        };
+
+        let mut selected = self.pane.buffer_id - 1;
+        self.open_popup(0)?;
+
+        let target = loop {
+            // the list is at the end of the buffers while it is open
+            let lines = render(&self.buffers);
+            let count = lines.len();
+            self.buffers.push(Buffer::new(lines, false, None, None));
+            if let Some(popup) = &mut self.popup {
+                popup.buffer_id = self.buffers.len() - 1;
+                popup.cursor.row = selected;
+                popup.scroll_to_cursor(&self.buffers)?;
+            }
+            self.refresh()?;
+            let event = self.ui.next_event().map_err(Error::UI)?;
+            self.buffers.pop();
+            match event {
+                Event::SpecialChar(EscapeSeq::UpArrow) => selected = selected.saturating_sub(1),
+                Event::SpecialChar(EscapeSeq::DownArrow) => {
+                    selected = (selected + 1).min(count - 1);
+                }
+                Event::NormalChar('\r') => break Some(selected + 1),
+                Event::NormalChar('d') => {
+                    // the list has been popped, so the popup can't be drawn while closing asks
+                    // about unsaved changes
+                    self.close_popup()?;
+                    self.close_buffer(selected + 1)?;
+                    self.open_popup(0)?;
+                    selected = selected.min(self.buffers.len() - 2);
+                }
+                Event::NormalChar('q' | '\x07') => break None,
+                _ => {}
+            }
+        };
+
+        self.close_popup()?;
+        match target {
+            Some(buffer_id) => self.switch_to(buffer_id),
+            None => Ok(()),
+        }
+    }
+
+    // take the swap file of a file that has just been loaded into a buffer, offering to recover
+    // the changes in it if the purport that wrote it crashed, or warn if another purport has the
+    // file open
+    fn open_swap(&mut self, buffer_id: usize, fp: &str, bytes: &[u8]) {
+        let Some(path) = swap::path(fp) else {
+            return;
+        };
+        let mut contents = bytes.to_vec();
+        if let Some(old) = swap::read(&path) {
+            if old.is_locked() {
+                self.pane.message = Some(format!(
+                    "{fp} is already open in another purport (pid {}), changes could be lost",
+                    old.pid
+                ));
+                return;
+            } else if old.pid != std::process::id()
+                && old.contents != bytes
+                && swap::is_newer(&path, fp)
+            {
+                let question = format!(
+                    "{fp} has unsaved changes from a purport that crashed, recover them (y/n): "
+                );
+                if self.confirm(&question).unwrap_or(false) {
+                    let buffer = &mut self.buffers[buffer_id];
+                    let disk = buffer.disk.take();
+                    let h = buffer.highlighter.take();
+                    *buffer = Buffer::decode(&old.contents, self.fallback, Some(fp.to_string()), h);
+                    buffer.disk = disk;
+                    buffer.dirty = true;
+                    contents = old.contents;
+                    self.pane.message = Some(format!("recovered the unsaved changes to {fp}"));
+                }
+            }
+        }
+        match swap::write(&path, &contents) {
+            Ok(()) => self.buffers[buffer_id].swap = Some(path),
+            Err(e) => {
+                self.pane.message = Some(format!("failed to write the swap file of {fp}: {e}"));
+            }
+        }
+    }
+
+    // write the swap files of every dirty buffer, returning whether any of them couldn't be,
+    // which is said in the status bar
+    fn write_swaps(&mut self) -> bool {
+        let failed: Vec<String> = self
+            .buffers
+            .iter()
+            .filter_map(|buffer| {
+                let path = buffer.swap.as_ref().filter(|_| buffer.dirty)?;
+                let contents = buffer.to_file().unwrap_or_else(|| buffer.to_chunk());
+                let e = swap::write(path, &contents).err()?;
+                Some(format!("{}: {e}", buffer.name()))
+            })
+            .collect();
+        if failed.is_empty() {
+            return false;
+        }
+        self.pane.message = Some(format!(
+            "failed to write the swap files of {}",
+            failed.join(", ")
+        ));
+        true
+    }
+
+    fn remove_swap(&mut self, path: &Path) {
+        if let Err(e) = swap::remove(path) {
+            self.pane.message = Some(format!(
+                "failed to remove the swap file {}: {e}",
+                path.display()
+            ));
+        }
+    }
+
     // we are currently highlighting relative to the bottom of the screen instead of line 0: TODO
     pub fn draw(&mut self) -> Result<(), Error> {
         #[cfg(debug_assertions)]
@@ -124,35 +584,94 @@ impl<U: UI> Editor<U> {
         let indent = " ".repeat(if self.pane.width <= self.pane.width / 2 - 15 {0} else {self.pane.width / 2 - 15});
         let welcome: Vec<Row> = format!("{}{}Welcome to Purport\n\n{}Ctrl-S to save\n{}Ctrl-Q to quit", margin, indent, indent, indent).split('\n').map(|line| line.chars().collect()).collect();
         */
-        let welcome = Vec::new();
-        let lines = self.pane.display(&self.buffers, &welcome)?;
+        let focused = self.arrange()?;
+        let area = self.area();
+        let explorer_row = self
+            .explorer
+            .as_mut()
+            .map(|explorer| explorer.scroll(area.height));
+        self.pane.indicator = Some(self.mode.indicator());
+        self.for_other_panes(|pane, _| {
+            pane.indicator = None;
+            Ok(())
+        })?;
+        self.ui.set_cursor_shape(self.mode.cursor_shape());
+        let mut lines = self.layout.display(&self.pane, &self.buffers)?;
+        if let Some(explorer) = &self.explorer {
+            let rows = explorer.display(area.height, area.col - 1, self.explorer_focused);
+            lines = rows
+                .into_iter()
+                .zip(lines)
+                .map(|(row, line)| {
+                    let mut segments = vec![row, Segment::Separator];
+                    segments.extend(line);
+                    segments
+                })
+                .collect();
+        }
+        let popup = match &self.popup {
+            Some(popup) => Some(popup.display(&self.buffers, &[])?),
+            None => None,
+        };
         let mut first = true;
-        for line in lines.chain(self.prompt.display(&self.buffers)?) {
+        if self.tabs.len() > 1 {
+            let labels: Vec<String> = self
+                .tabs
+                .iter()
+                .enumerate()
+                .map(|(idx, tab)| {
+                    let focused = if idx == self.tab {
+                        &self.pane
+                    } else {
+                        &tab.pane
+                    };
+                    tab::label(tab.name.as_deref(), focused, &self.buffers)
+                })
+                .collect();
+            for (piece, open) in tab::bar(&labels, self.tab, self.ui.width()) {
+                if open {
+                    self.ui.set_background(OPEN_TAB_COLOUR);
+                }
+                self.ui.draw(&piece);
+                if open {
+                    self.ui.set_background(ui::Colour::Reset);
+                }
+            }
+            first = false;
+        }
+        for line in lines.into_iter().chain(
+            popup
+                .into_iter()
+                .flatten()
+                .chain(self.prompt.display(&self.buffers)?)
+                .map(|row| vec![Segment::Row(row)]),
+        ) {
             if !first {
                 self.ui.newln();
             }
             first = false;
-            let line_highlighting = line.highlighting.clone();
-            for (col, ch) in line.enumerate() {
-                if let Some(c) = col.checked_sub(4) {
-                    if let Some(h) = line_highlighting.as_ref().and_then(|lh| lh.get(c)) {
-                        eprintln!("highlighting {:?}", h);
-                        self.ui.set_foreground(self.theme.get(h));
+            for segment in line {
+                match segment {
+                    Segment::Row(row) => draw_row(&mut self.ui, &self.theme, row),
+                    Segment::Separator => self.ui.draw("|"),
+                    Segment::Text { text, background } => {
+                        self.ui.set_background(background);
+                        self.ui.draw(&text);
+                        self.ui.set_background(ui::Colour::Reset);
                     }
                 }
-                match ch {
-                    Char::Normal(c) => self.ui.draw(&c.to_string()),
-                    Char::Grapheme(g) => self.ui.draw(g),
-                    Char::Foreground(c) => self.ui.set_foreground(c),
-                    Char::Background(c) => self.ui.set_background(c),
-                }
             }
-            self.ui.set_foreground(ui::Colour::Reset);
         }
-        self.ui.move_cursor(
-            self.pane.cursor.row + 1 - self.pane.offset.row,
-            self.pane.cursor.col + 5 - self.pane.offset.col,
-        );
+        if let Some(popup) = &self.popup {
+            let (row, col) = popup.screen_cursor(&self.buffers)?;
+            self.ui.move_cursor(self.height + row + 1, col + 1);
+        } else if let (true, Some(row)) = (self.explorer_focused, explorer_row) {
+            self.ui.move_cursor(area.row + row + 1, 1);
+        } else {
+            let (row, col) = self.pane.screen_cursor(&self.buffers)?;
+            self.ui
+                .move_cursor(focused.row + row + 1, focused.col + col + 1);
+        }
         #[cfg(debug_assertions)]
         {
             eprintln!("it took {:?} ms to refresh the editor", now.elapsed());
@@ -167,78 +686,690 @@ impl<U: UI> Editor<U> {
     // so much sharing of the UI would happen due to Editor::prompt that it is probably worth just
     // making it a field
     pub fn process_event(&mut self, event: &Event) -> Result<bool, Error> {
-        match event {
-            Event::SpecialChar(EscapeSeq::DownArrow) => {
+        self.pane.message = None;
+        if self.explorer_focused {
+            if let Some(res) = self.explorer_event(event) {
+                return res.map(|()| false);
+            }
+        }
+        if let Event::NormalChar('\r') = event {
+            if self.buffers[self.pane.buffer_id].hits.is_some() {
+                return self.jump_to_hit().map(|()| false);
+            }
+        }
+        if self.mode == Mode::Insert && self.recording {
+            self.keys.push(event.clone());
+        }
+        // the rest of a Normal mode command goes straight to it, so that the `w` of `dw` isn't
+        // looked up in the keymap
+        if self.pending.is_empty() {
+            self.chord.push(event.clone());
+            match self.keymap.lookup(self.mode, &self.chord) {
+                Lookup::Bound(binding) => {
+                    let Binding { command, arg } = binding.clone();
+                    self.chord.clear();
+                    let quit = self.execute(command, arg)?;
+                    self.fit_cursor()?;
+                    return Ok(quit);
+                }
+                Lookup::Prefix => {
+                    // show what has been typed so far, since nothing else happens until the
+                    // chord is finished
+                    self.pane.message = Some(format!("{}-", keymap::chord_name(&self.chord)));
+                    return Ok(false);
+                }
+                Lookup::Unbound => {}
+            }
+        }
+        // the keys of a chord that turned out not to be bound do what they would on their own
+        let keys = if self.chord.is_empty() {
+            vec![event.clone()]
+        } else {
+            mem::take(&mut self.chord)
+        };
+        for key in &keys {
+            self.unbound_key(key)?;
+        }
+        self.fit_cursor()?;
+        Ok(false)
+    }
+
+    // what a key that the keymap doesn't bind does, which is to be typed in Insert mode and to be
+    // part of a command in Normal and Visual mode
+    fn unbound_key(&mut self, key: &Event) -> Result<(), Error> {
+        if self.mode != Mode::Insert {
+            if let Some(res) = self.normal_event(key) {
+                return res;
+            }
+        }
+        match key {
+            Event::NormalChar(c) if !c.is_control() || *c == '\r' || *c == '\t' => {
+                self.pane.insert_grapheme(&mut self.buffers, &c.to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Run a command, with the argument it is bound with if it has one, returning whether the
+    /// editor should quit
+    #[allow(clippy::too_many_lines)]
+    pub fn execute(&mut self, command: Command, arg: Option<String>) -> Result<bool, Error> {
+        match command {
+            Command::MoveDown => self.pane.move_cursor_up_down(&self.buffers, 1),
+            Command::MoveUp => self.pane.move_cursor_up_down(&self.buffers, -1),
+            Command::MoveLeft => self.pane.move_cursor_left_right(&self.buffers, -1),
+            Command::MoveRight => self.pane.move_cursor_left_right(&self.buffers, 1),
+            Command::SelectDown => {
+                self.pane.start_selection();
                 self.pane.move_cursor_up_down(&self.buffers, 1)
             }
-            Event::SpecialChar(EscapeSeq::UpArrow) => {
+            Command::SelectUp => {
+                self.pane.start_selection();
                 self.pane.move_cursor_up_down(&self.buffers, -1)
             }
-            Event::SpecialChar(EscapeSeq::LeftArrow) => {
+            Command::SelectLeft => {
+                self.pane.start_selection();
                 self.pane.move_cursor_left_right(&self.buffers, -1)
             }
-            Event::SpecialChar(EscapeSeq::RightArrow) => {
+            Command::SelectRight => {
+                self.pane.start_selection();
                 self.pane.move_cursor_left_right(&self.buffers, 1)
             }
-            Event::NormalChar('\x01') => {
-                let text = self.prompt("text: ")?;
-                for c in text.chars() {
-                    self.pane
-                        .insert_grapheme(&mut self.buffers, &c.to_string())?;
+            Command::OpenFile => {
+                let file_name = self.argument(arg, "open: ")?;
+                if file_name.is_empty() {
+                    Ok(())
+                } else {
+                    self.open_file(file_name)
                 }
+            }
+            Command::CloseBuffer => self.close_buffer(self.pane.buffer_id),
+            Command::ListBuffers => self.list_buffers(),
+            Command::NextBuffer => self.cycle_buffers(true),
+            Command::PreviousBuffer => self.cycle_buffers(false),
+            Command::FindFile => self.find_file(),
+            Command::Palette => return self.command_palette(),
+            Command::SearchProject => self.search_project(),
+            Command::ReplaceProject => self.replace_project(),
+            Command::ToggleExplorer => {
+                self.toggle_explorer();
                 Ok(())
             }
-            Event::NormalChar('\x11') => {
-                return Ok(
-                    if self
-                        .buffers
-                        .get(self.pane.buffer_id)
-                        .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?
-                        .dirty
-                    {
-                        let mut should_quit = String::new();
-                        while should_quit != "y" && should_quit != "n" {
-                            should_quit = self
-                                .prompt(
-                                    "This file has unsaved changes do you want to quit (y/n): ",
-                                )?
-                                .to_ascii_lowercase();
-                        }
-                        should_quit == "y"
-                    } else {
-                        true
-                    },
-                );
+            Command::NewTab => self.new_tab(),
+            Command::CloseTab => self.close_tab(),
+            Command::RenameTab => self.rename_tab(arg),
+            Command::NextTab => self.switch_tab((self.tab + 1) % self.tabs.len()),
+            Command::PreviousTab => {
+                self.switch_tab((self.tab + self.tabs.len() - 1) % self.tabs.len())
+            }
+            Command::MoveTabLeft => {
+                self.move_tab(-1);
+                Ok(())
+            }
+            Command::MoveTabRight => {
+                self.move_tab(1);
+                Ok(())
+            }
+            Command::GotoTab => {
+                let number = self.argument(arg, "go to tab: ")?;
+                match number.trim().parse::<usize>() {
+                    Ok(number) if number > 0 => self.switch_tab(number - 1),
+                    _ => {
+                        self.pane.message = Some(format!("{number} isn't a tab number"));
+                        Ok(())
+                    }
+                }
+            }
+            Command::SplitBelow => {
+                self.layout.split(Direction::Rows, &self.pane);
+                Ok(())
+            }
+            Command::SplitRight => {
+                self.layout.split(Direction::Columns, &self.pane);
+                Ok(())
+            }
+            Command::ClosePane => self.close_pane(),
+            Command::FocusLeft => {
+                self.move_focus(Towards::Left);
+                Ok(())
+            }
+            Command::FocusDown => {
+                self.move_focus(Towards::Down);
+                Ok(())
+            }
+            Command::FocusUp => {
+                self.move_focus(Towards::Up);
+                Ok(())
+            }
+            Command::FocusRight => {
+                self.move_focus(Towards::Right);
+                Ok(())
+            }
+            Command::GrowHeight
+            | Command::ShrinkHeight
+            | Command::GrowWidth
+            | Command::ShrinkWidth => {
+                let direction = if let Command::GrowHeight | Command::ShrinkHeight = command {
+                    Direction::Rows
+                } else {
+                    Direction::Columns
+                };
+                let by = if let Command::GrowHeight | Command::GrowWidth = command {
+                    RESIZE_STEP
+                } else {
+                    -RESIZE_STEP
+                };
+                self.layout.resize(direction, by);
+                Ok(())
+            }
+            Command::InsertText => {
+                let text = self.argument(arg, "text: ")?;
+                self.pane.paste(&mut self.buffers, &text)
+            }
+            Command::ToggleSelection => {
+                self.pane.toggle_anchor();
+                Ok(())
+            }
+            Command::Copy => {
+                if let Some(text) = self.pane.selected_text(&self.buffers)? {
+                    self.registers.set(self.register.take(), text);
+                    self.pane.clear_selection();
+                }
+                Ok(())
+            }
+            Command::Cut => {
+                if let Some(text) = self.pane.delete_selection(&mut self.buffers)? {
+                    self.registers.set(self.register.take(), text);
+                }
+                Ok(())
+            }
+            Command::Paste => match self.registers.get(self.register.take()).map(String::from) {
+                Some(text) => self.pane.paste(&mut self.buffers, &text),
+                None => Ok(()),
+            },
+            Command::AddCursorBelow => self.pane.add_cursor_below(&self.buffers),
+            Command::AddCursorAtNextMatch => self.pane.add_cursor_at_next_match(&self.buffers),
+            Command::Cancel => {
+                self.pane.drop_cursors();
+                self.pane.search = None;
+                Ok(())
+            }
+            Command::Search => self.search(),
+            Command::Replace => self.replace(),
+            Command::ConvertFormat => self.convert_format(),
+            Command::SetFallbackEncoding => self.set_fallback_encoding(),
+            Command::ToggleHex => self.toggle_hex(),
+            Command::SetBackup => self.set_backup(),
+            Command::ToggleHexInsert => {
+                if let Some(hex) = &mut self.buffers[self.pane.buffer_id].hex {
+                    hex.insert = !hex.insert;
+                }
+                Ok(())
+            }
+            Command::SearchNext => self.search_next(true),
+            Command::SearchPrevious => self.search_next(false),
+            Command::ChooseRegister => {
+                self.register = self.argument(arg, "register: ")?.chars().next();
+                Ok(())
+            }
+            Command::Quit => {
+                let dirty: Vec<&str> = self.buffers[1..]
+                    .iter()
+                    .filter(|buffer| buffer.dirty)
+                    .map(Buffer::name)
+                    .collect();
+                return if dirty.is_empty() {
+                    Ok(true)
+                } else {
+                    let question = format!(
+                        "{} {} unsaved changes, quit anyway (y/n): ",
+                        dirty.join(", "),
+                        if dirty.len() == 1 { "has" } else { "have" }
+                    );
+                    self.confirm(&question)
+                };
+            }
+            Command::Save => self.save(self.pane.buffer_id),
+            Command::Undo => self.pane.undo(&mut self.buffers),
+            Command::Redo => self.pane.redo(&mut self.buffers),
+            Command::BrowseHistory => self.browse_history(),
+            Command::Backspace => self.pane.backspace(&mut self.buffers),
+            Command::NormalMode => {
+                if self.mode == Mode::Insert {
+                    self.enter_normal();
+                } else {
+                    self.leave_visual();
+                }
+                Ok(())
+            }
+            Command::InsertMode => {
+                self.leave_visual();
+                self.mode = Mode::Insert;
+                Ok(())
             }
-            Event::NormalChar('\x13') => self.save(self.pane.buffer_id),
-            Event::NormalChar('\x7f') => self.pane.backspace(&mut self.buffers),
-            Event::NormalChar(c) => self.pane.insert_grapheme(&mut self.buffers, &c.to_string()),
         }?;
         Ok(false)
     }
 
+    // the argument a command is bound with, or what the user answers when asked for it
+    fn argument(&mut self, arg: Option<String>, question: &str) -> Result<String, Error> {
+        match arg {
+            Some(arg) => Ok(arg),
+            None => self.prompt(question),
+        }
+    }
+
+    // handle a key in Normal or Visual mode, returning None for keys that aren't for these modes,
+    // like Alt and Ctrl combinations and the arrow keys, so that they still work as usual
+    fn normal_event(&mut self, event: &Event) -> Option<Result<(), Error>> {
+        if self.buffers.get(self.pane.buffer_id)?.hex.is_some() {
+            // hex digits are typed straight into the hex view
+            return None;
+        }
+        let key = match event {
+            Event::NormalChar('\r') => 'j',
+            Event::NormalChar('\x7f') => 'h',
+            Event::NormalChar('\t') => return Some(Ok(())),
+            Event::NormalChar(c) if !c.is_control() => *c,
+            Event::SpecialChar(EscapeSeq::Escape) => {
+                self.pending = Pending::default();
+                self.keys.clear();
+                self.leave_visual();
+                return Some(Ok(()));
+            }
+            _ => return None,
+        };
+        self.keys.push(event.clone());
+        Some(self.normal_key(key))
+    }
+
+    fn normal_key(&mut self, key: char) -> Result<(), Error> {
+        if self.mode != Mode::Normal && self.pending.is_empty() {
+            let operator = match key {
+                'd' | 'x' => Some(Operator::Delete),
+                'c' => Some(Operator::Change),
+                'y' => Some(Operator::Yank),
+                _ => None,
+            };
+            if let Some(operator) = operator {
+                self.keys.clear();
+                return self.visual_operate(operator);
+            }
+        }
+        let Some(command) = self.pending.push(key) else {
+            if self.pending.is_empty() {
+                self.keys.clear();
+            }
+            return Ok(());
+        };
+        let keys = mem::take(&mut self.keys);
+        if command.is_change() && self.mode == Mode::Normal && !self.replaying {
+            // a change that goes into Insert mode is only over once Insert mode is left
+            if command.is_insert() {
+                self.keys = keys;
+                self.recording = true;
+            } else {
+                self.last_change = keys;
+            }
+        }
+        self.normal_command(command)
+    }
+
+    fn normal_command(&mut self, command: normal::Command) -> Result<(), Error> {
+        let buffer = self
+            .buffers
+            .get(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?;
+        let cursor = self.pane.cursor;
+        let len = buffer.get(cursor.row).map_or(0, Line::len);
+        match command {
+            normal::Command::Move(motion, count) => {
+                self.pane.cursors.clear();
+                self.pane.cursor = normal::target(buffer, cursor, motion, count);
+                self.update_visual();
+                Ok(())
+            }
+            normal::Command::Visual { line } => {
+                self.toggle_visual(line);
+                Ok(())
+            }
+            // only motions work on the selection, apart from the operators
+            _ if self.mode != Mode::Normal => Ok(()),
+            normal::Command::Operate(operator, motion, count) => {
+                let range = normal::range(buffer, cursor, operator, motion, count);
+                self.operate(operator, range)
+            }
+            normal::Command::OperateLines(operator, count) => {
+                let last = cursor
+                    .row
+                    .saturating_add(count.saturating_sub(1))
+                    .min(buffer.lines().saturating_sub(1));
+                self.operate(operator, Range::Lines(cursor.row, last))
+            }
+            normal::Command::Insert(insert) => self.start_insert(insert),
+            normal::Command::DeleteChars(count) if cursor.col < len => {
+                let end = Cursor {
+                    col: cursor.col.saturating_add(count).min(len),
+                    ..cursor
+                };
+                self.operate(Operator::Delete, Range::Chars(cursor, end))
+            }
+            normal::Command::DeleteChars(_) => Ok(()),
+            normal::Command::Put { before, count } => self.put(before, count),
+            normal::Command::Undo(count) => {
+                for _ in 0..count {
+                    self.pane.undo(&mut self.buffers)?;
+                }
+                Ok(())
+            }
+            normal::Command::Repeat(count) => self.repeat(count),
+        }
+    }
+
+    // yank, delete or change some text, putting it in the register
+    fn operate(&mut self, operator: Operator, range: Range) -> Result<(), Error> {
+        let buffer = self
+            .buffers
+            .get(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?;
+        let len = |row| buffer.get(row).map_or(0, Line::len);
+        let (text, start, end) = match range {
+            Range::Lines(first, last) => {
+                let text = buffer.text_range((first, 0), (last, len(last)));
+                // deleting whole lines takes a line break with them, which is the one before
+                // them if they are at the end of the buffer
+                let (start, end) = if operator == Operator::Change {
+                    ((first, 0), (last, len(last)))
+                } else if last + 1 < buffer.lines() {
+                    ((first, 0), (last + 1, 0))
+                } else if first > 0 {
+                    ((first - 1, len(first - 1)), (last, len(last)))
+                } else {
+                    ((0, 0), (last, len(last)))
+                };
+                (text + "\n", start, end)
+            }
+            Range::Chars(start, end) => (
+                buffer.text_range((start.row, start.col), (end.row, end.col)),
+                (start.row, start.col),
+                (end.row, end.col),
+            ),
+        };
+        self.registers.set(self.register.take(), text);
+        self.pane.drop_cursors();
+        if operator == Operator::Yank {
+            self.pane.cursor = match range {
+                Range::Lines(first, _) => Cursor {
+                    row: first,
+                    ..self.pane.cursor
+                },
+                Range::Chars(start, _) => start,
+            };
+            return Ok(());
+        }
+        if start != end {
+            self.pane.anchor = Some(Cursor {
+                row: start.0,
+                col: start.1,
+            });
+            self.pane.cursor = Cursor {
+                row: end.0,
+                col: end.1,
+            };
+            self.pane.delete_selection(&mut self.buffers)?;
+        }
+        if let (Range::Lines(first, _), Operator::Delete) = (range, operator) {
+            let buffer = &self.buffers[self.pane.buffer_id];
+            let row = first.min(buffer.lines().saturating_sub(1));
+            self.pane.cursor = Cursor {
+                row,
+                col: normal::first_non_blank(buffer, row),
+            };
+        }
+        if operator == Operator::Change {
+            self.mode = Mode::Insert;
+        }
+        Ok(())
+    }
+
+    // go into Insert mode, moving the cursor to where typing starts
+    fn start_insert(&mut self, insert: Insert) -> Result<(), Error> {
+        let buffer = self
+            .buffers
+            .get(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?;
+        let Cursor { row, col } = self.pane.cursor;
+        let len = buffer.get(row).map_or(0, Line::len);
+        let first = normal::first_non_blank(buffer, row);
+        self.pane.drop_cursors();
+        self.mode = Mode::Insert;
+        match insert {
+            Insert::Before => {}
+            Insert::After => self.pane.cursor.col = (col + 1).min(len),
+            Insert::LineStart => self.pane.cursor.col = first,
+            Insert::LineEnd => self.pane.cursor.col = len,
+            Insert::LineBelow => {
+                self.pane.cursor.col = len;
+                self.pane.paste(&mut self.buffers, "\n")?;
+            }
+            Insert::LineAbove => {
+                self.pane.cursor.col = 0;
+                self.pane.paste(&mut self.buffers, "\n")?;
+                self.pane.cursor = Cursor { row, col: 0 };
+            }
+        }
+        self.pane.scroll_to_cursor(&self.buffers)
+    }
+
+    // put the text of the register after the cursor, or before it, on lines of its own if whole
+    // lines were yanked or deleted
+    fn put(&mut self, before: bool, count: usize) -> Result<(), Error> {
+        let Some(text) = self.registers.get(self.register.take()).map(String::from) else {
+            return Ok(());
+        };
+        let buffer = self
+            .buffers
+            .get(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?;
+        let Cursor { row, col } = self.pane.cursor;
+        let len = buffer.get(row).map_or(0, Line::len);
+        self.pane.drop_cursors();
+        if let Some(lines) = text.strip_suffix('\n') {
+            let lines = vec![lines; count].join("\n");
+            let (at, text) = if before {
+                (Cursor { row, col: 0 }, format!("{lines}\n"))
+            } else if row + 1 < buffer.lines() {
+                (
+                    Cursor {
+                        row: row + 1,
+                        col: 0,
+                    },
+                    format!("{lines}\n"),
+                )
+            } else {
+                (Cursor { row, col: len }, format!("\n{lines}"))
+            };
+            let row = if before { row } else { row + 1 };
+            self.pane.cursor = at;
+            self.pane.paste(&mut self.buffers, &text)?;
+            self.pane.cursor = Cursor {
+                row,
+                col: normal::first_non_blank(&self.buffers[self.pane.buffer_id], row),
+            };
+        } else {
+            self.pane.cursor.col = if before { col } else { (col + 1).min(len) };
+            self.pane.paste(&mut self.buffers, &text.repeat(count))?;
+            // the cursor ends up on the last character put, like in vi
+            self.pane.cursor.col = self.pane.cursor.col.saturating_sub(1);
+        }
+        Ok(())
+    }
+
+    // play back the keys of the last change
+    fn repeat(&mut self, count: usize) -> Result<(), Error> {
+        if self.replaying {
+            return Ok(());
+        }
+        let keys = self.last_change.clone();
+        self.replaying = true;
+        let res = keys
+            .iter()
+            .cycle()
+            .take(keys.len().saturating_mul(count))
+            .try_for_each(|event| self.process_event(event).map(|_| ()));
+        self.replaying = false;
+        res
+    }
+
+    // go into Visual mode, or Visual Line mode, or back to Normal mode if already in it
+    fn toggle_visual(&mut self, line: bool) {
+        let mode = if line { Mode::VisualLine } else { Mode::Visual };
+        if self.mode == mode {
+            self.leave_visual();
+            return;
+        }
+        if self.mode == Mode::Normal {
+            self.visual_start = self.pane.cursor;
+        }
+        self.mode = mode;
+        self.update_visual();
+    }
+
+    fn leave_visual(&mut self) {
+        if let Mode::Visual | Mode::VisualLine = self.mode {
+            self.mode = Mode::Normal;
+            self.pane.anchor = None;
+        }
+    }
+
+    // stretch the selection from where Visual mode started to the cursor, over whole lines in
+    // Visual Line mode
+    fn update_visual(&mut self) {
+        let start = self.visual_start;
+        self.pane.anchor = match self.mode {
+            Mode::Visual => Some(start),
+            Mode::VisualLine if self.pane.cursor < start => Some(Cursor {
+                col: self.buffers[self.pane.buffer_id]
+                    .get(start.row)
+                    .map_or(0, Line::len),
+                ..start
+            }),
+            Mode::VisualLine => Some(Cursor { col: 0, ..start }),
+            Mode::Insert | Mode::Normal => None,
+        };
+    }
+
+    // yank, delete or change the selection, which takes in the character under the cursor, and
+    // go back to Normal mode
+    fn visual_operate(&mut self, operator: Operator) -> Result<(), Error> {
+        let start = self.visual_start.min(self.pane.cursor);
+        let end = self.visual_start.max(self.pane.cursor);
+        let range = if self.mode == Mode::VisualLine {
+            Range::Lines(start.row, end.row)
+        } else {
+            let len = self.buffers[self.pane.buffer_id]
+                .get(end.row)
+                .map_or(0, Line::len);
+            Range::Chars(
+                start,
+                Cursor {
+                    col: (end.col + 1).min(len),
+                    ..end
+                },
+            )
+        };
+        self.leave_visual();
+        self.operate(operator, range)
+    }
+
+    // go back to Normal mode from Insert mode, which finishes the change that `.` repeats
+    fn enter_normal(&mut self) {
+        self.mode = Mode::Normal;
+        if mem::take(&mut self.recording) {
+            self.last_change = mem::take(&mut self.keys);
+        }
+        self.pane.drop_cursors();
+        if self.buffers[self.pane.buffer_id].hex.is_none() {
+            // the cursor goes back onto the last character typed, like in vi
+            self.pane.cursor.col = self.pane.cursor.col.saturating_sub(1);
+        }
+    }
+
+    // keep the cursor on a character outside of Insert mode, since it can't be past the end of
+    // the line there
+    fn fit_cursor(&mut self) -> Result<(), Error> {
+        let buffer = self
+            .buffers
+            .get(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?;
+        if self.mode != Mode::Insert && buffer.hex.is_none() {
+            let len = buffer.get(self.pane.cursor.row).map_or(0, Line::len);
+            self.pane.cursor.col = self.pane.cursor.col.min(len.saturating_sub(1));
+        }
+        self.pane.scroll_to_cursor(&self.buffers)
+    }
+
+    // ask a yes or no question until it gets an answer, taking no answer or cancelling the prompt
+    // as a no
+    fn confirm(&mut self, question: &str) -> Result<bool, Error> {
+        loop {
+            match self.prompt(question)?.to_ascii_lowercase().as_str() {
+                "y" => return Ok(true),
+                "n" | "" => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+
     pub fn prompt(&mut self, text: &str) -> Result<String, Error> {
-        self.prompt = Prompt::new(self.pane.width, 0, &mut self.buffers[..], text)?;
+        Ok(self
+            .prompt_with(text, |_, _, _| Ok(()))?
+            .unwrap_or_default())
+    }
+
+    // ask for some text like prompt, calling `update` with the text after every change to it and
+    // with any keys that the prompt doesn't use itself, returning None if it is cancelled with
+    // Ctrl-G
+    fn prompt_with(
+        &mut self,
+        text: &str,
+        mut update: impl FnMut(&mut Self, &str, Option<&Event>) -> Result<(), Error>,
+    ) -> Result<Option<String>, Error> {
+        self.prompt = Prompt::new(self.ui.width(), 0, &mut self.buffers[..], text)?;
         self.refresh()?;
         let res;
         loop {
             let ev = self.ui.next_event().map_err(Error::UI)?;
             match ev {
-                Event::SpecialChar(EscapeSeq::DownArrow | EscapeSeq::UpArrow) => continue,
                 Event::SpecialChar(EscapeSeq::LeftArrow) => {
                     self.prompt.move_cursor_left_right(&self.buffers, -1)
                 }
                 Event::SpecialChar(EscapeSeq::RightArrow) => {
                     self.prompt.move_cursor_left_right(&self.buffers, 1)
                 }
-                Event::NormalChar('\x7f') => self.prompt.backspace(&mut self.buffers),
+                Event::NormalChar('\x7f') => {
+                    self.prompt.backspace(&mut self.buffers)?;
+                    let input = self.prompt.take(&self.buffers)?;
+                    update(self, &input, None)
+                }
                 Event::NormalChar('\r') => {
-                    res = self.prompt.take(&self.buffers)?;
+                    res = Some(self.prompt.take(&self.buffers)?);
+                    break;
+                }
+                Event::NormalChar('\x07') => {
+                    res = None;
                     break;
                 }
-                Event::NormalChar(c) => self
-                    .prompt
-                    .insert_grapheme(&mut self.buffers, &c.to_string()),
+                Event::NormalChar(c) if !c.is_control() || c == '\t' => {
+                    self.prompt
+                        .insert_grapheme(&mut self.buffers, &c.to_string())?;
+                    let input = self.prompt.take(&self.buffers)?;
+                    update(self, &input, None)
+                }
+                ev => {
+                    let input = self.prompt.take(&self.buffers)?;
+                    update(self, &input, Some(&ev))
+                }
             }?;
             self.refresh()?;
         }
@@ -246,18 +1377,1198 @@ impl<U: UI> Editor<U> {
         Ok(res)
     }
 
-    pub fn mainloop(mut self) -> Result<(), Error> {
-        self.refresh()?;
-        loop {
-            let ev = self.ui.next_event().map_err(Error::UI)?;
-            if self.process_event(&ev)? {
-                break;
+    // search the current buffer as the pattern is typed: up and down go to the previous and next
+    // match, Ctrl-T changes how case is matched and Ctrl-R toggles regexes, while cancelling goes
+    // back to where the search started
+    pub fn search(&mut self) -> Result<(), Error> {
+        let (cursor, anchor, offset) = (self.pane.cursor, self.pane.anchor, self.pane.offset);
+        self.pane.message = Some(self.search.to_string());
+        let res = self.prompt_with("search: ", |editor, pattern, event| match event {
+            None => editor.start_search(pattern, cursor),
+            Some(Event::SpecialChar(EscapeSeq::DownArrow)) => editor.search_next(true),
+            Some(Event::SpecialChar(EscapeSeq::UpArrow)) => editor.search_next(false),
+            Some(Event::NormalChar('\x14')) => {
+                editor.search.cycle_case();
+                editor.start_search(pattern, cursor)
+            }
+            Some(Event::NormalChar('\x12')) => {
+                editor.search.regex = !editor.search.regex;
+                editor.start_search(pattern, cursor)
+            }
+            Some(_) => Ok(()),
+        })?;
+        if res.is_none() {
+            self.pane.cursor = cursor;
+            self.pane.anchor = anchor;
+            self.pane.offset = offset;
+            self.pane.search = None;
+            self.pane.message = None;
+        }
+        Ok(())
+    }
+
+    // compile a search pattern and move to its first match at or after `from`
+    fn start_search(&mut self, pattern: &str, from: Cursor) -> Result<(), Error> {
+        if pattern.is_empty() {
+            self.pane.search = None;
+            self.pane.message = Some(self.search.to_string());
+            return Ok(());
+        }
+        if let Ok(regex) = self.search.compile(pattern) {
+            self.pane.search = Some(regex);
+            self.goto_match(from, true)
+        } else {
+            self.pane.search = None;
+            self.pane.message = Some(format!("{}, invalid regex", self.search));
+            Ok(())
+        }
+    }
+
+    /// Move to the next or previous match of the current search
+    pub fn search_next(&mut self, forward: bool) -> Result<(), Error> {
+        let mut from = self.pane.cursor;
+        if forward {
+            from.col += 1;
+        }
+        self.goto_match(from, forward)
+    }
+
+    // replace every match of a pattern in the current buffer from the top, asking about each
+    // one unless told to replace all of them, as a single undoable step
+    pub fn replace(&mut self) -> Result<(), Error> {
+        let buffer_id = self.pane.buffer_id;
+        let buffer = self
+            .buffers
+            .get(buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(buffer_id))?;
+        if buffer.read_only {
+            self.pane.message = Some(String::from("the buffer is read only"));
+            return Ok(());
+        }
+        let pattern = self.prompt("replace: ")?;
+        if pattern.is_empty() {
+            return Ok(());
+        }
+        let Ok(regex) = self.search.compile(&pattern) else {
+            self.pane.message = Some(format!("{}, invalid regex", self.search));
+            return Ok(());
+        };
+        let template = self.prompt("with: ")?;
+        let buffer = self
+            .buffers
+            .get_mut(buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(buffer_id))?;
+        buffer.set_view(self.pane.cursor, self.pane.offset);
+        buffer.begin_group();
+        self.pane.drop_cursors();
+        self.pane.search = Some(regex.clone());
+        let mut from = Cursor::default();
+        let mut all = false;
+        let mut count = 0;
+        loop {
+            let buffer = self
+                .buffers
+                .get(buffer_id)
+                .ok_or(Error::BufferClosedPrematurely(buffer_id))?;
+            let found = match search::find(buffer, &regex, from, true) {
+                Some(found) if !found.wrapped => found,
+                _ => break,
+            };
+            if !all {
+                self.pane.anchor = Some(found.start);
+                self.pane.cursor = found.end;
+                self.pane.scroll_to_cursor(&self.buffers)?;
+                let answer = self.prompt("replace this match (y/n/a/q): ")?;
+                match answer.to_ascii_lowercase().as_str() {
+                    "y" => {}
+                    "n" => {
+                        from = found.end;
+                        continue;
+                    }
+                    "a" => all = true,
+                    "q" | "" => break,
+                    _ => continue,
+                }
+            }
+            let buffer = self
+                .buffers
+                .get_mut(buffer_id)
+                .ok_or(Error::BufferClosedPrematurely(buffer_id))?;
+            let text = if self.search.regex {
+                let line = buffer
+                    .get(found.start.row)
+                    .map_or(Line::default(), Clone::clone);
+                search::expand(&line, &regex, found.start.col, &template)
+            } else {
+                template.clone()
+            };
+            buffer.dirty = true;
+            buffer.delete_range(
+                (found.start.row, found.start.col),
+                (found.end.row, found.end.col),
+            );
+            let (row, col) = buffer.insert_text(found.start.row, found.start.col, &text);
+            from = Cursor { row, col };
+            self.pane.cursor = from;
+            count += 1;
+        }
+        self.pane.anchor = None;
+        self.pane.search = None;
+        self.pane.scroll_to_cursor(&self.buffers)?;
+        let buffer = self
+            .buffers
+            .get_mut(buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(buffer_id))?;
+        buffer.set_view(self.pane.cursor, self.pane.offset);
+        buffer.end_group();
+        self.pane.message = Some(format!(
+            "replaced {count} match{}",
+            if count == 1 { "" } else { "es" }
+        ));
+        Ok(())
+    }
+
+    // move the main cursor to the start of the next match from a position
+    fn goto_match(&mut self, from: Cursor, forward: bool) -> Result<(), Error> {
+        let Some(regex) = &self.pane.search else {
+            return Ok(());
+        };
+        let buffer = self
+            .buffers
+            .get(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?;
+        let mut message = self.search.to_string();
+        match search::find(buffer, regex, from, forward) {
+            Some(found) => {
+                if found.wrapped {
+                    message.push_str(if forward {
+                        ", wrapped around to the top"
+                    } else {
+                        ", wrapped around to the bottom"
+                    });
+                }
+                self.pane.drop_cursors();
+                self.pane.cursor = found.start;
+                self.pane.scroll_to_cursor(&self.buffers)?;
+            }
+            None => message.push_str(", no matches"),
+        }
+        self.pane.message = Some(message);
+        Ok(())
+    }
+
+    // change the line endings, final newline or BOM the current buffer is saved with
+    pub fn convert_format(&mut self) -> Result<(), Error> {
+        let words = self.prompt("convert to (lf/crlf eol/noeol bom/nobom): ")?;
+        let buffer = self
+            .buffers
+            .get_mut(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?;
+        if buffer.read_only {
+            self.pane.message = Some(String::from("the buffer is read only"));
+            return Ok(());
+        } else if buffer.hex.is_some() {
+            self.pane.message = Some(String::from("the buffer is being edited in hex"));
+            return Ok(());
+        }
+        let mut format = buffer.format;
+        match format.convert(&words) {
+            Ok(()) if format != buffer.format => {
+                buffer.format = format;
+                buffer.dirty = true;
+            }
+            Ok(()) => {}
+            Err(word) => self.pane.message = Some(format!("unknown format {word:?}")),
+        }
+        Ok(())
+    }
+
+    // switch the current buffer between being edited as text and in hex
+    pub fn toggle_hex(&mut self) -> Result<(), Error> {
+        let buffer = self
+            .buffers
+            .get_mut(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?;
+        let message = if buffer.hex.is_some() {
+            if buffer.stop_hex(self.fallback) {
+                "editing as text"
+            } else {
+                "can't edit as text: the bytes aren't text in any encoding we know"
+            }
+        } else if buffer.start_hex() {
+            "editing in hex"
+        } else {
+            "can't edit in hex: the buffer has characters that its encoding can't encode"
+        };
+        self.pane.message = Some(String::from(message));
+        self.pane.drop_cursors();
+        self.pane.cursor = Cursor::default();
+        self.pane.offset = Offset::default();
+        Ok(())
+    }
+
+    // choose what is kept of files when they are saved over
+    pub fn set_backup(&mut self) -> Result<(), Error> {
+        let name = self.prompt(&format!(
+            "backups (none/tilde/timestamp, now {}): ",
+            self.backup
+        ))?;
+        match Backup::from_name(name.trim()) {
+            Some(backup) => self.backup = backup,
+            None => self.pane.message = Some(format!("unknown kind of backup {name:?}")),
+        }
+        Ok(())
+    }
+
+    // choose the encoding that files which aren't Unicode are read with, reopening the current
+    // buffer with it if that wouldn't lose any changes
+    pub fn set_fallback_encoding(&mut self) -> Result<(), Error> {
+        let label = self.prompt("fallback encoding: ")?;
+        if let Some(Encoding::EightBit(encoding)) = Encoding::for_label(&label) {
+            self.fallback = encoding;
+        } else {
+            self.pane.message = Some(format!("{label:?} isn't a single byte encoding"));
+            return Ok(());
+        }
+        let buffer = self
+            .buffers
+            .get(self.pane.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.pane.buffer_id))?;
+        if !buffer.dirty && buffer.file_name.is_some() {
+            let file_name = buffer.file_name.clone();
+            self.load_into(self.pane.buffer_id, file_name);
+            self.pane.drop_cursors();
+            self.pane.cursor = Cursor::default();
+            self.pane.offset = Offset::default();
+        }
+        Ok(())
+    }
+
+    // open a popup of the undo tree of the current buffer under the main pane: moving through it
+    // previews each revision, enter keeps the selected one, q goes back to where we started and t
+    // jumps to the buffer as it was some number of minutes ago
+    pub fn browse_history(&mut self) -> Result<(), Error> {
+        let buffer_id = self.pane.buffer_id;
+        let render = |buffers: &[Buffer]| -> Result<(Vec<usize>, Vec<Line>, usize), Error> {
+            let history = buffers
+                .get(buffer_id)
+                .ok_or(Error::BufferClosedPrematurely(buffer_id))?
+                .history();
+            let (ids, lines): (Vec<_>, Vec<_>) = history
+                .render()
+                .into_iter()
+                .map(|(id, text)| (id, Line::new(text)))
+                .unzip();
+            let selected = ids
+                .iter()
+                .position(|id| *id == history.current())
+                .unwrap_or(0);
+            Ok((ids, lines, selected))
+        };
+
+        let (mut ids, lines, mut selected) = render(&self.buffers)?;
+        let original = ids[selected];
+        self.buffers.push(Buffer::new(lines, false, None, None));
+        let tree_id = self.buffers.len() - 1;
+        self.open_popup(tree_id)?;
+
+        loop {
+            if let Some(popup) = &mut self.popup {
+                popup.cursor.row = selected;
+                popup.scroll_to_cursor(&self.buffers)?;
+            }
+            self.refresh()?;
+            let target = match self.ui.next_event().map_err(Error::UI)? {
+                Event::SpecialChar(EscapeSeq::UpArrow) => selected.saturating_sub(1),
+                Event::SpecialChar(EscapeSeq::DownArrow) => (selected + 1).min(ids.len() - 1),
+                Event::NormalChar('\r') => break,
+                Event::NormalChar('q') => {
+                    self.pane.goto_revision(&mut self.buffers, original)?;
+                    break;
+                }
+                Event::NormalChar('t') => {
+                    let minutes = self.prompt("go back this many minutes: ")?;
+                    if let Ok(minutes) = minutes.trim().parse::<u64>() {
+                        let time = SystemTime::now()
+                            .checked_sub(Duration::from_secs(minutes * 60))
+                            .unwrap_or(SystemTime::UNIX_EPOCH);
+                        let revision = self.buffers[buffer_id].history().at_time(time);
+                        ids.iter()
+                            .position(|id| *id == revision)
+                            .unwrap_or(selected)
+                    } else {
+                        selected
+                    }
+                }
+                _ => selected,
+            };
+            if target != selected {
+                self.pane.goto_revision(&mut self.buffers, ids[target])?;
+                let (new_ids, lines, _) = render(&self.buffers)?;
+                ids = new_ids;
+                selected = target;
+                self.buffers[tree_id] = Buffer::new(lines, false, None, None);
+            }
+        }
+
+        self.buffers.pop();
+        self.close_popup()
+    }
+
+    // search every file in the project for a pattern, with the same options as searching a
+    // buffer, and list the matches in a read only buffer in a split below the focused pane
+    fn search_project(&mut self) -> Result<(), Error> {
+        let pattern = self.prompt(&format!("search project ({}): ", self.search))?;
+        if pattern.is_empty() {
+            return Ok(());
+        }
+        let regex = match self.search.compile(&pattern) {
+            Ok(regex) => regex,
+            Err(e) => {
+                self.pane.message = Some(format!("invalid pattern: {e}"));
+                return Ok(());
+            }
+        };
+        let hits = grep::search(Path::new("."), &regex);
+        if hits.is_empty() {
+            self.pane.message = Some(format!("no matches for {pattern} in the project"));
+            return Ok(());
+        }
+        let lines = hits.iter().map(|hit| Line::new(hit.to_string())).collect();
+        let mut results = Buffer::new(lines, false, None, None);
+        results.read_only = true;
+        results.hits = Some(hits);
+        // the results of the last search are replaced rather than piling up
+        let existing = (1..self.buffers.len()).find(|id| self.buffers[*id].hits.is_some());
+        let results_id = if let Some(results_id) = existing {
+            self.buffers[results_id] = results;
+            results_id
+        } else {
+            self.buffers.push(results);
+            self.buffers.len() - 1
+        };
+        if self.pane.buffer_id != results_id {
+            self.layout.split(Direction::Rows, &self.pane);
+            self.move_focus(Towards::Down);
+            self.switch_to(results_id)?;
+        }
+        self.for_other_panes(Pane::keep_in_buffer)?;
+        self.pane.cursor = Cursor::default();
+        self.pane.clamp_cursor(&self.buffers)
+    }
+
+    // replace a pattern in every file in the project, after going through a preview of the
+    // changes. Open files are changed through their buffers, so that the changes can be undone,
+    // and the rest are saved straight away.
+    fn replace_project(&mut self) -> Result<(), Error> {
+        let pattern = self.prompt(&format!("replace in project ({}): ", self.search))?;
+        if pattern.is_empty() {
+            return Ok(());
+        }
+        let regex = match self.search.compile(&pattern) {
+            Ok(regex) => regex,
+            Err(e) => {
+                self.pane.message = Some(format!("invalid pattern: {e}"));
+                return Ok(());
+            }
+        };
+        let template = self.prompt("with: ")?;
+        // resolve the files of the open buffers once, rather than once for every file walked
+        let open: HashMap<PathBuf, usize> = (1..self.buffers.len())
+            .filter_map(|id| {
+                let fp = self.buffers[id].file_name.as_deref()?;
+                let resolved = save::resolve(Path::new(fp))
+                    .and_then(fs::canonicalize)
+                    .ok()?;
+                Some((resolved, id))
+            })
+            .collect();
+        let mut hunks = Vec::new();
+        for path in ignore::walk(Path::new(".")) {
+            let name = display_path(&path);
+            let buffer_id = fs::canonicalize(&path)
+                .ok()
+                .and_then(|resolved| open.get(&resolved).copied());
+            if let Some(buffer_id) = buffer_id {
+                let buffer = &self.buffers[buffer_id];
+                if buffer.hex.is_some() || buffer.read_only {
+                    continue;
+                }
+                let lines = (0..buffer.lines())
+                    .filter_map(|row| buffer.get(row))
+                    .map(|line| line.skip(0));
+                hunks.extend(grep::hunks(
+                    &name,
+                    lines,
+                    &regex,
+                    &template,
+                    self.search.regex,
+                ));
+            } else if let Some(text) = grep::read_text(&path) {
+                hunks.extend(grep::hunks(
+                    &name,
+                    text.lines(),
+                    &regex,
+                    &template,
+                    self.search.regex,
+                ));
+            }
+        }
+        if hunks.is_empty() {
+            self.pane.message = Some(format!("no matches for {pattern} in the project"));
+            return Ok(());
+        }
+        if let Some(hunks) = self.preview_hunks(hunks)? {
+            self.apply_hunks(&hunks)
+        } else {
+            self.pane.message = Some(String::from("cancelled replacing"));
+            Ok(())
+        }
+    }
+
+    // list the changes that a replace would make by file, letting each one be turned on and off,
+    // and return them once they are accepted or None if they are cancelled
+    fn preview_hunks(&mut self, mut hunks: Vec<Hunk>) -> Result<Option<Vec<Hunk>>, Error> {
+        // the lines of the preview and the row that each hunk starts on
+        let render = |hunks: &[Hunk]| -> (Vec<Line>, Vec<usize>) {
+            let mut lines = Vec::new();
+            let mut rows = Vec::new();
+            let mut path = None;
+            for hunk in hunks {
+                if path != Some(&hunk.path) {
+                    lines.push(Line::new(hunk.path.clone()));
+                    path = Some(&hunk.path);
+                }
+                rows.push(lines.len());
+                let check = if hunk.enabled { 'x' } else { ' ' };
+                let prefix = format!("  [{check}] {}: ", hunk.row + 1);
+                let indent = " ".repeat(prefix.len());
+                lines.push(Line::new(format!("{prefix}- {}", hunk.before)));
+                lines.push(Line::new(format!("{indent}+ {}", hunk.after)));
+            }
+            (lines, rows)
+        };
+
+        let (lines, mut rows) = render(&hunks);
+        self.buffers.push(Buffer::new(lines, false, None, None));
+        let preview_id = self.buffers.len() - 1;
+        self.open_popup(preview_id)?;
+        let mut selected = 0;
+        self.pane.message = Some(String::from(
+            "space toggles a change, f toggles its file, enter replaces, q cancels",
+        ));
+        let accepted = loop {
+            if let Some(popup) = &mut self.popup {
+                // on the box of the selected hunk
+                popup.cursor = Cursor {
+                    row: rows[selected],
+                    col: 3,
+                };
+                popup.scroll_to_cursor(&self.buffers)?;
             }
             self.refresh()?;
+            match self.ui.next_event().map_err(Error::UI)? {
+                Event::SpecialChar(EscapeSeq::UpArrow) => selected = selected.saturating_sub(1),
+                Event::SpecialChar(EscapeSeq::DownArrow) => {
+                    selected = (selected + 1).min(hunks.len() - 1);
+                }
+                Event::NormalChar(' ') => hunks[selected].enabled = !hunks[selected].enabled,
+                Event::NormalChar('f') => {
+                    let path = hunks[selected].path.clone();
+                    let enabled = !hunks[selected].enabled;
+                    for hunk in hunks.iter_mut().filter(|hunk| hunk.path == path) {
+                        hunk.enabled = enabled;
+                    }
+                }
+                Event::NormalChar('\r') => break true,
+                Event::NormalChar('q' | '\x07') => break false,
+                _ => continue,
+            }
+            let (lines, new_rows) = render(&hunks);
+            rows = new_rows;
+            self.buffers[preview_id] = Buffer::new(lines, false, None, None);
+        };
+        self.pane.message = None;
+        self.buffers.pop();
+        self.close_popup()?;
+        Ok(accepted.then_some(hunks))
+    }
+
+    // make the enabled changes of a replace across the project
+    fn apply_hunks(&mut self, hunks: &[Hunk]) -> Result<(), Error> {
+        let (mut changed, mut stale, mut files) = (0, 0, 0);
+        let mut failed = Vec::new();
+        let mut start = 0;
+        while start < hunks.len() {
+            // the hunks of each file are next to each other
+            let path = &hunks[start].path;
+            let end = start
+                + hunks[start..]
+                    .iter()
+                    .take_while(|hunk| hunk.path == *path)
+                    .count();
+            let enabled: Vec<&Hunk> = hunks[start..end]
+                .iter()
+                .filter(|hunk| hunk.enabled)
+                .collect();
+            start = end;
+            if enabled.is_empty() {
+                continue;
+            }
+            files += 1;
+            if let Some(buffer_id) = self.find_buffer(path) {
+                let view = if buffer_id == self.pane.buffer_id {
+                    View {
+                        cursor: self.pane.cursor,
+                        offset: self.pane.offset,
+                    }
+                } else {
+                    self.buffers[buffer_id].view
+                };
+                let buffer = &mut self.buffers[buffer_id];
+                buffer.set_view(view.cursor, view.offset);
+                buffer.begin_group();
+                for hunk in enabled {
+                    let Some(len) = buffer
+                        .get(hunk.row)
+                        .filter(|line| line.skip(0) == hunk.before)
+                        .map(Line::len)
+                    else {
+                        stale += 1;
+                        continue;
+                    };
+                    buffer.dirty = true;
+                    buffer.delete_range((hunk.row, 0), (hunk.row, len));
+                    buffer.insert_text(hunk.row, 0, &hunk.after);
+                    changed += 1;
+                }
+                buffer.end_group();
+            } else {
+                let Some(text) = grep::read_text(Path::new(path)) else {
+                    stale += enabled.len();
+                    continue;
+                };
+                let (text, skipped) = grep::apply(&text, &enabled);
+                stale += skipped;
+                match save::write(path, text.as_bytes(), self.backup) {
+                    Ok(()) => changed += enabled.len() - skipped,
+                    Err(_) => failed.push(path.clone()),
+                }
+            }
+        }
+        self.pane.keep_in_buffer(&self.buffers)?;
+        self.for_other_panes(Pane::keep_in_buffer)?;
+
+        let mut message = format!(
+            "replaced {changed} line{} in {files} file{}",
+            if changed == 1 { "" } else { "s" },
+            if files == 1 { "" } else { "s" }
+        );
+        if stale > 0 {
+            message = format!("{message}, {stale} changed since the preview and were skipped");
+        }
+        if !failed.is_empty() {
+            message = format!("{message}, failed to write {}", failed.join(", "));
+        }
+        self.pane.message = Some(message);
+        Ok(())
+    }
+
+    // open the file of the search result under the cursor at the match, in the pane above the
+    // results if there is one
+    fn jump_to_hit(&mut self) -> Result<(), Error> {
+        let Some(hit) = self.buffers[self.pane.buffer_id]
+            .hits
+            .as_ref()
+            .and_then(|hits| hits.get(self.pane.cursor.row))
+            .cloned()
+        else {
+            return Ok(());
+        };
+        self.move_focus(Towards::Up);
+        self.open_file(hit.path)?;
+        self.pane.cursor = hit.cursor;
+        self.pane.clamp_cursor(&self.buffers)
+    }
+
+    // open a file from anywhere in the project by typing part of its path: the best matches are
+    // listed above the prompt as it is typed, and up and down choose between them
+    fn find_file(&mut self) -> Result<(), Error> {
+        let files: Vec<String> = ignore::walk(Path::new("."))
+            .iter()
+            .map(|path| display_path(path))
+            .collect();
+        match self.choose("find file: ", &files, &files)? {
+            Some(idx) => self.open_file(files[idx].clone()),
+            None => Ok(()),
+        }
+    }
+
+    // list every command along with the keys it is bound to in the current mode, and run the
+    // chosen one, which asks for its argument if it takes one
+    fn command_palette(&mut self) -> Result<bool, Error> {
+        let commands: Vec<Command> = Command::ALL
+            .iter()
+            .copied()
+            .filter(|command| *command != Command::Palette)
+            .collect();
+        let names: Vec<&str> = commands.iter().map(|command| command.name()).collect();
+        let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+        let labels: Vec<String> = commands
+            .iter()
+            .map(|command| match self.keymap.keys_for(self.mode, *command) {
+                Some(keys) => format!("{:<width$}  {}", command.name(), keymap::chord_name(&keys)),
+                None => command.name().to_string(),
+            })
+            .collect();
+        match self.choose("command: ", &names, &labels)? {
+            Some(idx) => self.execute(commands[idx], None),
+            None => Ok(false),
+        }
+    }
+
+    // pick one of some candidates by fuzzy matching what is typed against them, with the best
+    // matches listed by their labels in a popup, returning None if nothing is picked
+    fn choose<S: AsRef<str>>(
+        &mut self,
+        question: &str,
+        candidates: &[S],
+        labels: &[String],
+    ) -> Result<Option<usize>, Error> {
+        self.buffers
+            .push(Buffer::new(vec![Line::default()], false, None, None));
+        let list_id = self.buffers.len() - 1;
+        self.open_popup(list_id)?;
+        let mut ranked = fuzzy::rank("", candidates, MAX_MATCHES);
+        let mut selected = 0;
+        self.show_matches(list_id, labels, &ranked, selected)?;
+        let chosen = self.prompt_with(question, |ed, text, event| {
+            match event {
+                Some(Event::SpecialChar(EscapeSeq::UpArrow)) => {
+                    selected = selected.saturating_sub(1);
+                }
+                Some(Event::SpecialChar(EscapeSeq::DownArrow)) => {
+                    selected = (selected + 1).min(ranked.len().saturating_sub(1));
+                }
+                Some(_) => return Ok(()),
+                None => {
+                    ranked = fuzzy::rank(text, candidates, MAX_MATCHES);
+                    selected = 0;
+                }
+            }
+            ed.show_matches(list_id, labels, &ranked, selected)
+        })?;
+        self.buffers.pop();
+        self.close_popup()?;
+        Ok(chosen.and_then(|_| ranked.get(selected).copied()))
+    }
+
+    // fill a popup with a list of matches, marking the selected one
+    fn show_matches(
+        &mut self,
+        list_id: usize,
+        candidates: &[String],
+        ranked: &[usize],
+        selected: usize,
+    ) -> Result<(), Error> {
+        let mut lines: Vec<Line> = ranked
+            .iter()
+            .enumerate()
+            .map(|(row, idx)| {
+                let marker = if row == selected { "> " } else { "  " };
+                Line::new(format!("{marker}{}", candidates[*idx]))
+            })
+            .collect();
+        if lines.is_empty() {
+            lines.push(Line::new(String::from("  no matches")));
+        }
+        self.buffers[list_id] = Buffer::new(lines, false, None, None);
+        if let Some(popup) = &mut self.popup {
+            popup.cursor = Cursor::default();
+            popup.cursor.row = selected;
+            popup.scroll_to_cursor(&self.buffers)?;
+        }
+        Ok(())
+    }
+
+    // open a popup along the bottom of the screen, taking a third of the rows from the panes
+    fn open_popup(&mut self, buffer_id: usize) -> Result<(), Error> {
+        let height = (self.height / 3).max(1);
+        self.height -= height;
+        self.arrange()?;
+        self.popup = Some(Pane {
+            buffer_id,
+            width: self.ui.width(),
+            height,
+            ..Pane::default()
+        });
+        Ok(())
+    }
+
+    fn close_popup(&mut self) -> Result<(), Error> {
+        if let Some(popup) = self.popup.take() {
+            self.height += popup.height;
+        }
+        self.arrange().map(|_| ())
+    }
+
+    // fit the panes into the rows they have, keeping their cursors in view, and return where the
+    // focused pane is
+    fn arrange(&mut self) -> Result<Rect, Error> {
+        let area = self.area();
+        let focused = self.layout.focused_rect(&mut self.pane, area);
+        self.pane.scroll_to_cursor(&self.buffers)?;
+        for pane in self.layout.panes() {
+            pane.scroll_to_cursor(&self.buffers)?;
+        }
+        Ok(focused)
+    }
+
+    // move the focus to the nearest pane in a direction
+    fn move_focus(&mut self, towards: Towards) {
+        let area = self.area();
+        let moved = self.layout.move_focus(&mut self.pane, area, towards);
+        // the file explorer is to the left of every pane
+        if !moved && towards == Towards::Left && self.explorer.is_some() {
+            self.focus_explorer();
+        }
+    }
+
+    // the part of the screen that the layout of the open tab covers, below the tab bar if there
+    // is one and beside the file explorer if it is open
+    fn area(&self) -> Rect {
+        let top = usize::from(self.tabs.len() > 1);
+        let left = if self.explorer.is_some() {
+            // the explorer and its separator
+            (self.ui.width() / 3).min(EXPLORER_WIDTH) + 1
+        } else {
+            0
+        };
+        Rect {
+            row: top,
+            col: left,
+            width: self.ui.width().saturating_sub(left),
+            height: self.height.saturating_sub(top),
+        }
+    }
+
+    /// Open the file explorer on a directory and give it the focus
+    pub fn open_explorer(&mut self, root: PathBuf) {
+        self.explorer = Some(Explorer::new(root));
+        self.explorer_focused = true;
+    }
+
+    // open the file explorer in the working directory, focus it if it is already open, or close
+    // it if it already has the focus
+    fn toggle_explorer(&mut self) {
+        if self.explorer.is_none() {
+            self.open_explorer(PathBuf::from("."));
+        } else if self.explorer_focused {
+            self.explorer = None;
+            self.explorer_focused = false;
+        } else {
+            self.focus_explorer();
+        }
+    }
+
+    fn focus_explorer(&mut self) {
+        if let Some(explorer) = &mut self.explorer {
+            // things may well have changed since it last had the focus
+            explorer.refresh();
+            self.explorer_focused = true;
+        }
+    }
+
+    // handle a key while the file explorer has the focus, returning None for keys that aren't
+    // the explorer's, like Alt and Ctrl combinations, so that they still work as usual
+    fn explorer_event(&mut self, event: &Event) -> Option<Result<(), Error>> {
+        let explorer = self.explorer.as_mut()?;
+        match event {
+            Event::SpecialChar(EscapeSeq::UpArrow) | Event::NormalChar('k') => {
+                explorer.move_selection(-1);
+            }
+            Event::SpecialChar(EscapeSeq::DownArrow) | Event::NormalChar('j') => {
+                explorer.move_selection(1);
+            }
+            Event::SpecialChar(EscapeSeq::RightArrow) | Event::NormalChar('l') => {
+                explorer.expand();
+            }
+            Event::SpecialChar(EscapeSeq::LeftArrow) | Event::NormalChar('h') => {
+                explorer.collapse();
+            }
+            Event::NormalChar('i') => {
+                explorer.show_ignored = !explorer.show_ignored;
+                explorer.refresh();
+            }
+            Event::NormalChar('\r' | 'o') => return Some(self.open_selected()),
+            Event::NormalChar('a') => return Some(self.create_in_explorer()),
+            Event::NormalChar('r') => return Some(self.rename_in_explorer()),
+            Event::NormalChar('d') => return Some(self.delete_in_explorer()),
+            Event::NormalChar('q') => {
+                self.explorer = None;
+                self.explorer_focused = false;
+            }
+            Event::NormalChar('\x07')
+            | Event::SpecialChar(EscapeSeq::Alt('l') | EscapeSeq::Escape) => {
+                self.explorer_focused = false;
+            }
+            Event::NormalChar(c) if c.is_control() => return None,
+            Event::SpecialChar(EscapeSeq::Alt(_)) => return None,
+            _ => {}
+        }
+        Some(Ok(()))
+    }
+
+    // expand or collapse the selected directory, or open the selected file in the focused pane
+    fn open_selected(&mut self) -> Result<(), Error> {
+        let Some(explorer) = &mut self.explorer else {
+            return Ok(());
+        };
+        let Some(entry) = explorer.selected_entry() else {
+            return Ok(());
+        };
+        if entry.is_dir {
+            explorer.toggle();
+            return Ok(());
+        }
+        let path = entry.path.clone();
+        self.explorer_focused = false;
+        self.open_file(display_path(&path))
+    }
+
+    // make a new file, or a directory if the name ends with a slash, where the selection is
+    fn create_in_explorer(&mut self) -> Result<(), Error> {
+        let Some(dir) = self.explorer.as_ref().map(Explorer::target_dir) else {
+            return Ok(());
+        };
+        let name = self.prompt(&format!(
+            "new file in {} (end with / for a directory): ",
+            display_path(&dir)
+        ))?;
+        if name.is_empty() {
+            return Ok(());
+        }
+        let path = dir.join(&name);
+        let res = if name.ends_with('/') {
+            fs::create_dir_all(&path)
+        } else {
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| {
+                    fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)
+                })
+                .map(|_| ())
+        };
+        match res {
+            Ok(()) => {
+                if let Some(explorer) = &mut self.explorer {
+                    explorer.reveal(&path);
+                }
+            }
+            Err(e) => {
+                self.pane.message = Some(format!("failed to create {}: {e}", display_path(&path)));
+            }
+        }
+        Ok(())
+    }
+
+    // rename or move the selected entry, keeping the buffers of any open files in it pointed at
+    // where they went
+    fn rename_in_explorer(&mut self) -> Result<(), Error> {
+        let Some(entry) = self
+            .explorer
+            .as_ref()
+            .and_then(Explorer::selected_entry)
+            .filter(|entry| entry.depth > 0)
+            .cloned()
+        else {
+            return Ok(());
+        };
+        let name = self.prompt(&format!("rename {} to: ", display_path(&entry.path)))?;
+        if name.is_empty() {
+            return Ok(());
+        }
+        let to = entry.path.with_file_name(&name);
+        if to == entry.path {
+            return Ok(());
+        }
+        if let Ok(metadata) = fs::symlink_metadata(&to) {
+            let existing = display_path(&to);
+            if metadata.is_dir() {
+                self.pane.message = Some(format!("{existing} is already a directory"));
+                return Ok(());
+            }
+            if !self.confirm(&format!("{existing} already exists, replace it (y/n): "))? {
+                return Ok(());
+            }
+        }
+        let old = fs::canonicalize(&entry.path).unwrap_or_else(|_| entry.path.clone());
+        let moved: Vec<(usize, PathBuf)> = (1..self.buffers.len())
+            .filter_map(|buffer_id| {
+                let fp = self.buffers[buffer_id].file_name.as_deref()?;
+                let resolved = fs::canonicalize(fp).ok()?;
+                let inside = resolved.strip_prefix(&old).ok()?;
+                Some((buffer_id, inside.to_path_buf()))
+            })
+            .collect();
+        if let Err(e) = fs::rename(&entry.path, &to) {
+            self.pane.message = Some(format!(
+                "failed to rename {}: {e}",
+                display_path(&entry.path)
+            ));
+            return Ok(());
+        }
+        for (buffer_id, inside) in moved {
+            let path = if inside.as_os_str().is_empty() {
+                to.clone()
+            } else {
+                to.join(inside)
+            };
+            let fp = display_path(&path);
+            if let Some(swap) = self.buffers[buffer_id].swap.take() {
+                self.remove_swap(&swap);
+            }
+            let buffer = &mut self.buffers[buffer_id];
+            buffer.swap = swap::path(&fp);
+            buffer.disk = fs::read(&fp).ok().map(|bytes| Stamp::new(&fp, &bytes));
+            buffer.file_name = Some(fp);
+        }
+        if let Some(explorer) = &mut self.explorer {
+            explorer.reveal(&to);
+        }
+        Ok(())
+    }
+
+    // delete the selected file, or directory with everything in it, once it has been confirmed
+    fn delete_in_explorer(&mut self) -> Result<(), Error> {
+        let Some(entry) = self
+            .explorer
+            .as_ref()
+            .and_then(Explorer::selected_entry)
+            .filter(|entry| entry.depth > 0)
+            .cloned()
+        else {
+            return Ok(());
+        };
+        let name = display_path(&entry.path);
+        let question = if entry.is_dir {
+            format!("delete {name} and everything in it (y/n): ")
+        } else {
+            format!("delete {name} (y/n): ")
+        };
+        if !self.confirm(&question)? {
+            return Ok(());
+        }
+        let res = if entry.is_dir {
+            fs::remove_dir_all(&entry.path)
+        } else {
+            fs::remove_file(&entry.path)
+        };
+        if let Err(e) = res {
+            self.pane.message = Some(format!("failed to delete {name}: {e}"));
+        }
+        if let Some(explorer) = &mut self.explorer {
+            explorer.refresh();
+        }
+        Ok(())
+    }
+
+    // run something on every pane but the focused one, in every tab
+    fn for_other_panes(
+        &mut self,
+        mut f: impl FnMut(&mut Pane, &[Buffer]) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let open = self.tab;
+        let others = self
+            .tabs
+            .iter_mut()
+            .enumerate()
+            .filter(|(idx, _)| *idx != open)
+            .flat_map(|(_, tab)| tab.panes());
+        for pane in self.layout.panes().chain(others) {
+            f(pane, &self.buffers)?;
+        }
+        Ok(())
+    }
+
+    // open a new tab after the open one, showing the same buffer as the focused pane
+    fn new_tab(&mut self) -> Result<(), Error> {
+        let tab = Tab {
+            pane: self.pane.clone(),
+            ..Tab::default()
+        };
+        self.tabs.insert(self.tab + 1, tab);
+        self.switch_tab(self.tab + 1)
+    }
+
+    fn switch_tab(&mut self, idx: usize) -> Result<(), Error> {
+        if idx == self.tab || idx >= self.tabs.len() {
+            return Ok(());
+        }
+        let open = &mut self.tabs[self.tab];
+        open.pane = mem::take(&mut self.pane);
+        open.layout = mem::take(&mut self.layout);
+        self.take_tab(idx)
+    }
+
+    // make a tab the open one, taking its panes out of it
+    fn take_tab(&mut self, idx: usize) -> Result<(), Error> {
+        let tab = &mut self.tabs[idx];
+        self.pane = mem::take(&mut tab.pane);
+        self.layout = mem::take(&mut tab.layout);
+        self.tab = idx;
+        self.arrange().map(|_| ())
+    }
+
+    // close the open tab, unless it is the only one. Its buffers stay open, so nothing is lost.
+    fn close_tab(&mut self) -> Result<(), Error> {
+        if self.tabs.len() == 1 {
+            self.pane.message = Some(String::from("can't close the only tab"));
+            return Ok(());
+        }
+        self.tabs.remove(self.tab);
+        self.take_tab(self.tab.min(self.tabs.len() - 1))
+    }
+
+    // give the open tab a name, or go back to naming it after its buffer if the name is empty
+    fn rename_tab(&mut self, name: Option<String>) -> Result<(), Error> {
+        let name = self.argument(name, "rename tab: ")?;
+        self.tabs[self.tab].name = if name.is_empty() { None } else { Some(name) };
+        Ok(())
+    }
+
+    // swap the open tab with the one `by` places away, stopping at either end
+    fn move_tab(&mut self, by: isize) {
+        let to = self.tab.saturating_add_signed(by).min(self.tabs.len() - 1);
+        self.tabs.swap(self.tab, to);
+        self.tab = to;
+    }
+
+    // close the focused pane, unless it is the only one
+    fn close_pane(&mut self) -> Result<(), Error> {
+        if let Some(pane) = self.layout.close() {
+            self.pane = pane;
+            self.arrange().map(|_| ())
+        } else {
+            self.pane.message = Some(String::from("can't close the only pane"));
+            Ok(())
+        }
+    }
+
+    pub fn mainloop(mut self) -> Result<(), Error> {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.run())) {
+            Ok(res) => res,
+            Err(payload) => {
+                let saved = self.save_for_recovery();
+                // drop the ui before printing anything, so that it doesn't get cleared away
+                drop(self);
+                for (name, path) in saved {
+                    match path {
+                        Ok(path) => eprintln!("saved the changes to {name} in {}", path.display()),
+                        Err(e) => eprintln!("failed to save the changes to {name}: {e:?}"),
+                    }
+                }
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    // write every dirty buffer somewhere it can be recovered from after a crash, returning the
+    // names of the buffers and where each one went. Swap files are brought up to date too, so that
+    // opening the files again offers the changes back.
+    fn save_for_recovery(&mut self) -> Vec<(String, io::Result<PathBuf>)> {
+        self.write_swaps();
+        self.buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.dirty && buffer.is_norm())
+            .map(|(buffer_id, buffer)| {
+                let name = buffer
+                    .file_name
+                    .clone()
+                    .unwrap_or_else(|| format!("buffer {buffer_id}"));
+                let file = PathBuf::from(&name).file_name().map_or_else(
+                    || String::from("unnamed"),
+                    |name| name.to_string_lossy().into_owned(),
+                );
+                let contents = buffer.to_file().unwrap_or_else(|| buffer.to_chunk());
+                (name, swap::recover(&file, &contents))
+            })
+            .collect()
+    }
+
+    fn run(&mut self) -> Result<(), Error> {
+        self.refresh()?;
+        loop {
+            let event = self.ui.poll_event(WATCH_INTERVAL).map_err(Error::UI)?;
+            if let Some(ev) = &event {
+                if self.process_event(ev)? {
+                    break;
+                }
+                self.swap_pending.get_or_insert_with(Instant::now);
+            }
+            let mut failed = false;
+            if self
+                .swap_pending
+                .is_some_and(|since| since.elapsed() >= SWAP_INTERVAL)
+            {
+                failed = self.write_swaps();
+                self.swap_pending = None;
+            }
+            if self.check_files()? || failed || event.is_some() {
+                // edits through one pane can leave the cursors of others past the end of the
+                // buffer
+                self.for_other_panes(Pane::keep_in_buffer)?;
+                self.refresh()?;
+            }
+        }
+        // there's nowhere left to say that a swap file couldn't be removed
+        for buffer in &mut self.buffers {
+            if let Some(path) = buffer.swap.take() {
+                let _ = swap::remove(&path);
+            }
         }
         self.refresh()
     }
 
+    // reload clean buffers whose files have been changed by something else, and warn about dirty
+    // ones, returning whether anything happened
+    fn check_files(&mut self) -> Result<bool, Error> {
+        let Some(watcher) = &mut self.watcher else {
+            return Ok(false);
+        };
+        let mut noticed = false;
+        for path in watcher.changed() {
+            for buffer_id in 0..self.buffers.len() {
+                let buffer = &self.buffers[buffer_id];
+                let Some(fp) = buffer.file_name.clone() else {
+                    continue;
+                };
+                if !watch::is_file(&fp, &path)
+                    || !buffer.disk.as_ref().is_some_and(|disk| disk.changed(&fp))
+                {
+                    continue;
+                }
+                noticed = true;
+                if buffer.dirty {
+                    self.pane.message = Some(format!(
+                        "{fp} has changed since it was opened, saving will overwrite it"
+                    ));
+                } else {
+                    self.load_into(buffer_id, Some(fp.clone()));
+                    if self.pane.buffer_id == buffer_id {
+                        self.pane.clamp_cursor(&self.buffers)?;
+                    }
+                    self.pane.message = Some(format!("reloaded {fp} because it changed"));
+                }
+            }
+        }
+        Ok(noticed)
+    }
+
     pub fn refresh(&mut self) -> Result<(), Error> {
         self.draw()?;
         self.ui.refresh().map_err(Error::UI)
@@ -282,3 +2593,43 @@ impl Default for GlobalSystemData {
         }
     }
 }
+
+// how a path is shown and given to open_file, without a leading `./`
+fn display_path(path: &Path) -> String {
+    path.strip_prefix(".")
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+// draw a row of a pane, colouring its text with the highlighting of the line
+fn draw_row<U: UI>(ui: &mut U, theme: &Theme, row: RowIter) {
+    let line_highlighting = row.highlighting.clone();
+    // only characters that take up a column count towards the highlighting position
+    let mut col: usize = 0;
+    let mut foreground = ui::Colour::Reset;
+    for ch in row {
+        if let Char::Normal(_) | Char::Grapheme(_) = ch {
+            if let Some(c) = col.checked_sub(4) {
+                if let Some(h) = line_highlighting.as_ref().and_then(|lh| lh.get(c)) {
+                    foreground = theme.get(h);
+                    ui.set_foreground(foreground);
+                }
+            }
+            col += 1;
+        }
+        match ch {
+            Char::Normal(c) => ui.draw(&c.to_string()),
+            Char::Grapheme(g) => ui.draw(g),
+            Char::Foreground(c) => ui.set_foreground(c),
+            Char::Background(c) => {
+                ui.set_background(c);
+                // resetting the background resets the foreground as well
+                if c == ui::Colour::Reset {
+                    ui.set_foreground(foreground);
+                }
+            }
+        }
+    }
+    ui.set_foreground(ui::Colour::Reset);
+}
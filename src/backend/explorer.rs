@@ -0,0 +1,263 @@
+use super::ignore::Ignore;
+use super::layout::Segment;
+use crate::frontend::ui::Colour;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use unicode_segmentation::UnicodeSegmentation;
+
+// the background of the selected entry, which is dimmer while the explorer doesn't have focus
+const SELECTED_COLOUR: Colour = Colour::Blue;
+const UNFOCUSED_COLOUR: Colour = Colour::Black;
+
+/// A file or directory shown in the explorer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub is_dir: bool,
+}
+
+/// The tree of files under a directory that the file explorer side pane shows
+#[derive(Clone, Debug)]
+pub struct Explorer {
+    pub root: PathBuf,
+    // the directories whose contents are shown
+    expanded: HashSet<PathBuf>,
+    /// Every visible entry from the top, starting with the root
+    pub entries: Vec<Entry>,
+    pub selected: usize,
+    // the first entry on screen
+    offset: usize,
+    /// Whether entries that git ignores are shown as well
+    pub show_ignored: bool,
+}
+
+impl Explorer {
+    pub fn new(root: PathBuf) -> Explorer {
+        let mut explorer = Explorer {
+            root,
+            expanded: HashSet::new(),
+            entries: Vec::new(),
+            selected: 0,
+            offset: 0,
+            show_ignored: false,
+        };
+        explorer.refresh();
+        explorer
+    }
+
+    /// Read the tree from disk again, keeping the same entry selected if it is still there
+    pub fn refresh(&mut self) {
+        let selected = self.selected_entry().map(|entry| entry.path.clone());
+        let mut ignore = Ignore::new(&self.root);
+        let mut entries = vec![Entry {
+            path: self.root.clone(),
+            depth: 0,
+            is_dir: true,
+        }];
+        self.push_children(&self.root, 1, &mut ignore, &mut entries);
+        self.entries = entries;
+        self.selected = selected
+            .and_then(|selected| self.entries.iter().position(|entry| entry.path == selected))
+            .unwrap_or_else(|| self.selected.min(self.entries.len() - 1));
+    }
+
+    // add the entries in a directory, directories first, and the entries of any that are expanded
+    fn push_children(
+        &self,
+        dir: &Path,
+        depth: usize,
+        ignore: &mut Ignore,
+        entries: &mut Vec<Entry>,
+    ) {
+        let Ok(read) = fs::read_dir(dir) else {
+            return;
+        };
+        let mut children: Vec<Entry> = read
+            .filter_map(Result::ok)
+            .map(|child| {
+                let path = child.path();
+                Entry {
+                    is_dir: path.is_dir(),
+                    path,
+                    depth,
+                }
+            })
+            .filter(|child| self.show_ignored || !ignore.is_ignored(&child.path, child.is_dir))
+            .collect();
+        children.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.path.cmp(&b.path)));
+        for child in children {
+            let expand = child.is_dir && self.expanded.contains(&child.path);
+            let path = child.path.clone();
+            entries.push(child);
+            if expand {
+                self.push_children(&path, depth + 1, ignore, entries);
+            }
+        }
+    }
+
+    pub fn selected_entry(&self) -> Option<&Entry> {
+        self.entries.get(self.selected)
+    }
+
+    pub fn move_selection(&mut self, by: isize) {
+        self.selected = self
+            .selected
+            .saturating_add_signed(by)
+            .min(self.entries.len() - 1);
+    }
+
+    /// Show or hide what is in the selected directory
+    pub fn toggle(&mut self) {
+        match self.selected_entry() {
+            Some(entry) if entry.is_dir && entry.depth > 0 => {
+                let path = entry.path.clone();
+                if !self.expanded.remove(&path) {
+                    self.expanded.insert(path);
+                }
+                self.refresh();
+            }
+            _ => {}
+        }
+    }
+
+    pub fn expand(&mut self) {
+        if let Some(entry) = self.selected_entry() {
+            if entry.is_dir && self.expanded.insert(entry.path.clone()) {
+                self.refresh();
+            }
+        }
+    }
+
+    /// Hide what is in the selected directory, or select the directory the selected entry is in
+    /// if it is already hidden
+    pub fn collapse(&mut self) {
+        let Some(entry) = self.selected_entry().cloned() else {
+            return;
+        };
+        if entry.is_dir && self.expanded.remove(&entry.path) {
+            self.refresh();
+        } else if let Some(parent) = self.entries[..self.selected]
+            .iter()
+            .rposition(|parent| parent.depth < entry.depth)
+        {
+            self.selected = parent;
+        }
+    }
+
+    /// Select a path, expanding the directories it is in
+    pub fn reveal(&mut self, path: &Path) {
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(&self.root) {
+                break;
+            }
+            self.expanded.insert(dir.to_path_buf());
+        }
+        self.refresh();
+        if let Some(idx) = self.entries.iter().position(|entry| entry.path == path) {
+            self.selected = idx;
+        }
+    }
+
+    /// The directory that new files go in: the selected one, or the one the selected file is in
+    pub fn target_dir(&self) -> PathBuf {
+        match self.selected_entry() {
+            Some(entry) if entry.is_dir => entry.path.clone(),
+            Some(entry) => entry
+                .path
+                .parent()
+                .map_or_else(|| self.root.clone(), Path::to_path_buf),
+            None => self.root.clone(),
+        }
+    }
+
+    /// Scroll so that the selected entry is one of the `height` rows on screen, returning which
+    /// row it is on
+    pub fn scroll(&mut self, height: usize) -> usize {
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height;
+        }
+        self.selected - self.offset
+    }
+
+    /// The rows of the explorer, `width` columns wide
+    pub fn display(&self, height: usize, width: usize, focused: bool) -> Vec<Segment<'static>> {
+        (self.offset..self.offset + height)
+            .map(|idx| {
+                let text = self
+                    .entries
+                    .get(idx)
+                    .map(|entry| self.entry_text(entry))
+                    .unwrap_or_default();
+                let mut text: String = text.graphemes(true).take(width).collect();
+                let len = text.graphemes(true).count();
+                text.extend(std::iter::repeat_n(' ', width - len));
+                let background = match (idx == self.selected, focused) {
+                    (true, true) => SELECTED_COLOUR,
+                    (true, false) => UNFOCUSED_COLOUR,
+                    (false, _) => Colour::Reset,
+                };
+                Segment::Text { text, background }
+            })
+            .collect()
+    }
+
+    fn entry_text(&self, entry: &Entry) -> String {
+        let name = if entry.depth == 0 {
+            self.root.to_string_lossy()
+        } else {
+            entry.path.file_name().unwrap_or_default().to_string_lossy()
+        };
+        let marker = match (
+            entry.is_dir,
+            entry.depth == 0 || self.expanded.contains(&entry.path),
+        ) {
+            (true, true) => "v ",
+            (true, false) => "> ",
+            (false, _) => "  ",
+        };
+        let slash = if entry.is_dir { "/" } else { "" };
+        format!("{}{marker}{name}{slash}", "  ".repeat(entry.depth))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::TempDir;
+
+    #[test]
+    fn expand_and_hide_ignored() {
+        let temp = TempDir::new("explorer");
+        let root = temp.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        fs::write(root.join("src").join("main.rs"), "").unwrap();
+        fs::write(root.join("README"), "").unwrap();
+        let mut explorer = Explorer::new(root.to_path_buf());
+        let names = |explorer: &Explorer| -> Vec<String> {
+            explorer
+                .entries
+                .iter()
+                .map(|entry| explorer.entry_text(entry).trim().to_string())
+                .collect()
+        };
+        assert_eq!(names(&explorer)[1..], ["> src/", ".gitignore", "README"]);
+
+        explorer.move_selection(1);
+        explorer.toggle();
+        assert_eq!(names(&explorer)[1..3], ["v src/", "main.rs"]);
+        explorer.move_selection(1);
+        explorer.collapse();
+        assert_eq!(explorer.selected, 1);
+        assert_eq!(explorer.target_dir(), root.join("src"));
+
+        explorer.show_ignored = true;
+        explorer.refresh();
+        assert_eq!(names(&explorer)[1..4], ["v src/", "main.rs", "> target/"]);
+    }
+}
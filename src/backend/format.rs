@@ -0,0 +1,277 @@
+use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// A single byte encoding, used for files that aren't valid UTF-8 and don't start with a BOM
+    EightBit(&'static encoding_rs::Encoding),
+}
+
+impl Encoding {
+    /// The encoding a label such as `utf-16le` or `latin1` refers to, if it is one we can read and
+    /// write without losing anything
+    pub fn for_label(label: &str) -> Option<Encoding> {
+        let encoding = encoding_rs::Encoding::for_label(label.as_bytes())?;
+        if encoding == UTF_8 {
+            Some(Encoding::Utf8)
+        } else if encoding == UTF_16LE {
+            Some(Encoding::Utf16Le)
+        } else if encoding == UTF_16BE {
+            Some(Encoding::Utf16Be)
+        } else if encoding.is_single_byte() {
+            Some(Encoding::EightBit(encoding))
+        } else {
+            None
+        }
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => b"\xef\xbb\xbf",
+            Encoding::Utf16Le => b"\xff\xfe",
+            Encoding::Utf16Be => b"\xfe\xff",
+            Encoding::EightBit(_) => b"",
+        }
+    }
+
+    // decode text, as long as encoding it again gives back exactly the same bytes
+    fn decode(self, bytes: &[u8]) -> Option<String> {
+        let text = match self {
+            Encoding::Utf8 => return String::from_utf8(bytes.to_vec()).ok(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let pairs = bytes.chunks_exact(2);
+                if !pairs.remainder().is_empty() {
+                    return None;
+                }
+                let units = pairs.map(|pair| {
+                    let pair = [pair[0], pair[1]];
+                    if self == Encoding::Utf16Le {
+                        u16::from_le_bytes(pair)
+                    } else {
+                        u16::from_be_bytes(pair)
+                    }
+                });
+                return char::decode_utf16(units).collect::<Result<_, _>>().ok();
+            }
+            Encoding::EightBit(encoding) => encoding
+                .decode_without_bom_handling_and_without_replacement(bytes)?
+                .into_owned(),
+        };
+        (self.encode(&text)? == bytes).then_some(text)
+    }
+
+    // encode text, or None if it has characters that the encoding can't represent
+    fn encode(self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            Encoding::Utf8 => Some(text.as_bytes().to_vec()),
+            Encoding::Utf16Le => Some(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            Encoding::Utf16Be => Some(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+            Encoding::EightBit(encoding) => {
+                let (bytes, _, unmappable) = encoding.encode(text);
+                (!unmappable).then(|| bytes.into_owned())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Encoding::Utf8 => write!(f, "UTF-8"),
+            Encoding::Utf16Le => write!(f, "UTF-16LE"),
+            Encoding::Utf16Be => write!(f, "UTF-16BE"),
+            Encoding::EightBit(encoding) => write!(f, "{}", encoding.name()),
+        }
+    }
+}
+
+/// Everything about how a file is laid out that isn't part of its lines, so that saving writes
+/// it back the way it was found
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Format {
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+    pub final_newline: bool,
+    pub bom: bool,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format {
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            bom: false,
+        }
+    }
+}
+
+impl Format {
+    /// Work out the format of a file and decode it, using the fallback for files that aren't
+    /// Unicode, or None if it looks like a binary file or couldn't be saved again unchanged.
+    ///
+    /// Files only count as CRLF if every line ends that way, so that the stray carriage returns
+    /// of files with mixed endings are kept.
+    pub fn decode(
+        bytes: &[u8],
+        fallback: &'static encoding_rs::Encoding,
+    ) -> Option<(Format, String)> {
+        let unicode = [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be];
+        let (encoding, bom, text) = if let Some(encoding) = unicode
+            .iter()
+            .copied()
+            .find(|encoding| bytes.starts_with(encoding.bom()))
+        {
+            let text = encoding.decode(&bytes[encoding.bom().len()..])?;
+            (encoding, true, text)
+        } else if bytes.contains(&0) {
+            return None;
+        } else if let Ok(text) = std::str::from_utf8(bytes) {
+            (Encoding::Utf8, false, text.to_string())
+        } else {
+            let encoding = Encoding::EightBit(fallback);
+            (encoding, false, encoding.decode(bytes)?)
+        };
+        // every piece but the last is followed by a newline
+        let mut ended = text.split('\n').rev().skip(1).peekable();
+        let crlf = ended.peek().is_some() && ended.all(|line| line.ends_with('\r'));
+        let format = Format {
+            encoding,
+            line_ending: if crlf {
+                LineEnding::CrLf
+            } else {
+                LineEnding::Lf
+            },
+            final_newline: text.ends_with('\n'),
+            bom,
+        };
+        Some((format, text))
+    }
+
+    /// Split decoded text in this format into its lines
+    pub fn split(self, text: &str) -> Vec<&str> {
+        let text = if self.final_newline {
+            text.strip_suffix(self.line_ending.as_str()).unwrap_or(text)
+        } else {
+            text
+        };
+        text.split('\n')
+            .map(|line| match self.line_ending {
+                LineEnding::CrLf => line.strip_suffix('\r').unwrap_or(line),
+                LineEnding::Lf => line,
+            })
+            .collect()
+    }
+
+    /// Join lines back into the contents of a file in this format, or None if they can't be
+    /// encoded
+    pub fn encode<'a>(self, lines: impl Iterator<Item = &'a str>) -> Option<Vec<u8>> {
+        let mut text = String::new();
+        for (i, line) in lines.enumerate() {
+            if i > 0 {
+                text.push_str(self.line_ending.as_str());
+            }
+            text.push_str(line);
+        }
+        if self.final_newline {
+            text.push_str(self.line_ending.as_str());
+        }
+        let mut bytes = Vec::new();
+        if self.bom {
+            bytes.extend_from_slice(self.encoding.bom());
+        }
+        bytes.extend(self.encoding.encode(&text)?);
+        Some(bytes)
+    }
+
+    /// Change the format from a space separated list of `lf`, `crlf`, `eol`, `noeol`, `bom`,
+    /// `nobom` and encoding names, returning the first word that isn't one of them
+    pub fn convert<'a>(&mut self, words: &'a str) -> Result<(), &'a str> {
+        for word in words.split_whitespace() {
+            match word {
+                "lf" => self.line_ending = LineEnding::Lf,
+                "crlf" => self.line_ending = LineEnding::CrLf,
+                "eol" => self.final_newline = true,
+                "noeol" => self.final_newline = false,
+                "bom" => self.bom = true,
+                "nobom" => self.bom = false,
+                _ => self.encoding = Encoding::for_label(word).ok_or(word)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.encoding,
+            match self.line_ending {
+                LineEnding::Lf => "LF",
+                LineEnding::CrLf => "CRLF",
+            }
+        )?;
+        if !self.final_newline {
+            write!(f, " noeol")?;
+        }
+        if self.bom && !self.encoding.bom().is_empty() {
+            write!(f, " BOM")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::WINDOWS_1252;
+
+    fn round_trip(bytes: &[u8]) -> Format {
+        let (format, text) = Format::decode(bytes, WINDOWS_1252).unwrap();
+        let lines = format.split(&text);
+        assert_eq!(format.encode(lines.into_iter()).unwrap(), bytes);
+        format
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(round_trip(b"a\nb\n"), Format::default());
+        let format = round_trip(b"\xef\xbb\xbfa\r\nb");
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert!(format.bom && !format.final_newline);
+        assert_eq!(format.split("a\r\nb"), vec!["a", "b"]);
+        assert_eq!(round_trip(b"a\r\nb\n").line_ending, LineEnding::Lf);
+        round_trip(b"");
+        round_trip(b"\r\n");
+    }
+
+    #[test]
+    fn encodings() {
+        assert_eq!(round_trip(b"\xff\xfea\0\n\0").encoding, Encoding::Utf16Le);
+        assert_eq!(round_trip(b"\xfe\xff\0a\0\n").encoding, Encoding::Utf16Be);
+        let format = round_trip(b"caf\xe9\n");
+        assert_eq!(format.encoding, Encoding::EightBit(WINDOWS_1252));
+        assert!(Format::decode(b"\x7fELF\0\0", WINDOWS_1252).is_none());
+        assert!(format.encode(std::iter::once("\u{2606}")).is_none());
+    }
+}
@@ -0,0 +1,108 @@
+// what each matched character is worth, on top of any bonus
+const MATCH: i64 = 16;
+// for a character straight after the one matched before it
+const CONSECUTIVE: i64 = 8;
+// for a character at the start of a word, such as after a `/` or `_`
+const BOUNDARY: i64 = 8;
+// taken off for every character skipped between two matched ones
+const GAP: i64 = 1;
+
+/// How well a pattern matches a candidate, or None if the characters of the pattern aren't all in
+/// it in order. Characters at the start of words and runs of consecutive characters count for
+/// more, while gaps between them count against it. Case only matters if the pattern has capitals
+/// in it.
+#[allow(clippy::cast_possible_wrap)]
+pub fn score(pattern: &str, candidate: &str) -> Option<i64> {
+    let smart_case = pattern.chars().any(char::is_uppercase);
+    let fold = |c: char| {
+        if smart_case {
+            c
+        } else {
+            c.to_lowercase().next().unwrap_or(c)
+        }
+    };
+    let pattern: Vec<char> = pattern
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(fold)
+        .collect();
+    let Some((first, rest)) = pattern.split_first() else {
+        return Some(0);
+    };
+    let chars: Vec<char> = candidate.chars().collect();
+    let folded: Vec<char> = chars.iter().copied().map(fold).collect();
+    let bonus: Vec<i64> = (0..chars.len())
+        .map(|idx| match idx.checked_sub(1).map(|prev| chars[prev]) {
+            None | Some('_' | '-' | '.' | ' ') => BOUNDARY,
+            // the start of a file or directory name is the best place to match
+            Some('/') => BOUNDARY + 2,
+            Some(prev) if prev.is_lowercase() && chars[idx].is_uppercase() => BOUNDARY - 1,
+            Some(_) => 0,
+        })
+        .collect();
+
+    // the best score for the pattern so far, with its last character matched at each index
+    let mut best: Vec<Option<i64>> = folded
+        .iter()
+        .zip(&bonus)
+        .map(|(c, bonus)| (c == first).then_some(MATCH + bonus))
+        .collect();
+    for p in rest {
+        let mut next = vec![None; folded.len()];
+        // the best score to continue from after a gap, plus the penalty of a gap up to here, so
+        // that it only has to be taken off once the gap ends
+        let mut carried: Option<i64> = None;
+        for idx in 1..folded.len() {
+            if folded[idx] == *p {
+                let consecutive = best[idx - 1].map(|score| score + CONSECUTIVE);
+                let gapped = carried.map(|score| score - GAP * idx as i64 + bonus[idx]);
+                next[idx] = consecutive.max(gapped).map(|score| score + MATCH);
+            }
+            carried = carried.max(best[idx - 1].map(|score| score + GAP * idx as i64));
+        }
+        best = next;
+    }
+    best.into_iter().flatten().max()
+}
+
+/// The indices of the candidates that match a pattern, best first, keeping at most `limit` of
+/// them. Shorter candidates win ties.
+pub fn rank<S: AsRef<str>>(pattern: &str, candidates: &[S], limit: usize) -> Vec<usize> {
+    let mut scored: Vec<(i64, usize, usize)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, candidate)| {
+            let candidate = candidate.as_ref();
+            score(pattern, candidate).map(|score| (-score, candidate.len(), idx))
+        })
+        .collect();
+    scored.sort_unstable();
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, _, idx)| idx)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rank_paths() {
+        let paths = [
+            "src/backend/editor.rs",
+            "src/frontend/ui.rs",
+            "src/backend/explorer.rs",
+            "README.md",
+            "examples/demo_editor.rs",
+        ];
+        assert_eq!(score("xyz", paths[0]), None);
+        assert_eq!(rank("editor", &paths, 10), vec![0, 4]);
+        assert_eq!(rank("bexp", &paths, 10), vec![2]);
+        assert_eq!(rank("ui", &paths, 10)[0], 1);
+        assert_eq!(rank("rs", &paths, 2), vec![1, 0]);
+        assert_eq!(rank("README", &paths, 10), vec![3]);
+        assert!(rank("readme", &paths, 10).contains(&3));
+    }
+}
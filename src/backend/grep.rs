@@ -0,0 +1,206 @@
+use super::buffer::Line;
+use super::cursor::Cursor;
+use super::ignore;
+use super::search;
+use regex::{NoExpand, Regex};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// the most matches that a search of the project lists
+const MAX_HITS: usize = 10_000;
+// how much of the start of a file is looked at to tell whether it is binary
+const SNIFF_LEN: usize = 8 * 1024;
+
+/// A match found by searching the files of a project
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hit {
+    pub path: String,
+    /// Where the match starts, counting graphemes along the line
+    pub cursor: Cursor,
+    /// The line the match is on
+    pub text: String,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.path,
+            self.cursor.row + 1,
+            self.cursor.col + 1,
+            self.text
+        )
+    }
+}
+
+/// A line of a file that replacing across the project would change
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    pub path: String,
+    pub row: usize,
+    pub before: String,
+    pub after: String,
+    /// Whether the change is going to be made
+    pub enabled: bool,
+}
+
+/// The text of a file that can be searched, which leaves out binary files and files that aren't
+/// UTF-8
+pub fn read_text(path: &Path) -> Option<String> {
+    let bytes = fs::read(path).ok()?;
+    if bytes[..bytes.len().min(SNIFF_LEN)].contains(&0) {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Every match of a regex in the files under a directory that git wouldn't ignore
+pub fn search(root: &Path, regex: &Regex) -> Vec<Hit> {
+    let mut hits = Vec::new();
+    for path in ignore::walk(root) {
+        let Some(text) = read_text(&path) else {
+            continue;
+        };
+        let name = path.strip_prefix(".").unwrap_or(&path).to_string_lossy();
+        for (row, text) in text.lines().enumerate() {
+            let line = Line::new(text.to_string());
+            for (col, _) in search::matches(&line, regex) {
+                if hits.len() == MAX_HITS {
+                    return hits;
+                }
+                hits.push(Hit {
+                    path: name.to_string(),
+                    cursor: Cursor { row, col },
+                    text: text.to_string(),
+                });
+            }
+        }
+    }
+    hits
+}
+
+/// The lines of a file that replacing every match of a regex would change. Capture groups like
+/// `$1` in the template are filled in if `expand` is set, otherwise it is used as it is.
+pub fn hunks<S: AsRef<str>>(
+    path: &str,
+    lines: impl IntoIterator<Item = S>,
+    regex: &Regex,
+    template: &str,
+    expand: bool,
+) -> Vec<Hunk> {
+    lines
+        .into_iter()
+        .enumerate()
+        .filter_map(|(row, line)| {
+            let line = line.as_ref();
+            let after = if expand {
+                regex.replace_all(line, template)
+            } else {
+                regex.replace_all(line, NoExpand(template))
+            };
+            (after != line).then(|| Hunk {
+                path: path.to_string(),
+                row,
+                before: line.to_string(),
+                after: after.into_owned(),
+                enabled: true,
+            })
+        })
+        .collect()
+}
+
+/// Make the changes of some hunks to the text of their file, keeping its line endings. Hunks
+/// whose line isn't what it was when they were made are left out, and how many of them there were
+/// is returned as well.
+pub fn apply(text: &str, hunks: &[&Hunk]) -> (String, usize) {
+    let mut applied = String::with_capacity(text.len());
+    let mut stale = 0;
+    for (row, piece) in text.split_inclusive('\n').enumerate() {
+        let content = piece.strip_suffix('\n').map_or(piece, |content| {
+            content.strip_suffix('\r').unwrap_or(content)
+        });
+        match hunks.iter().find(|hunk| hunk.row == row) {
+            Some(hunk) if hunk.before == content => {
+                applied.push_str(&hunk.after);
+                applied.push_str(&piece[content.len()..]);
+            }
+            Some(_) => {
+                stale += 1;
+                applied.push_str(piece);
+            }
+            None => applied.push_str(piece),
+        }
+    }
+    // hunks past the end of the file are stale too
+    let rows = text.split_inclusive('\n').count();
+    stale += hunks.iter().filter(|hunk| hunk.row >= rows).count();
+    (applied, stale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::TempDir;
+
+    #[test]
+    fn search_project() {
+        let temp = TempDir::new("grep");
+        let root = temp.path();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join(".gitignore"), "*.bak\n").unwrap();
+        fs::write(
+            root.join("src").join("main.rs"),
+            "fn main() {\n    é main();\n}\n",
+        )
+        .unwrap();
+        fs::write(root.join("main.bak"), "main\n").unwrap();
+        fs::write(root.join("data"), b"main\0").unwrap();
+        let regex = Regex::new("main").unwrap();
+        let hits: Vec<String> = search(root, &regex)
+            .iter()
+            .map(|hit| {
+                let path = Path::new(&hit.path).strip_prefix(root).unwrap();
+                format!(
+                    "{}",
+                    Hit {
+                        path: path.display().to_string(),
+                        ..hit.clone()
+                    }
+                )
+            })
+            .collect();
+        assert_eq!(
+            hits,
+            [
+                "src/main.rs:1:4: fn main() {",
+                "src/main.rs:2:7:     é main();"
+            ]
+        );
+    }
+
+    #[test]
+    fn replace_lines() {
+        let text = "let a = 1;\r\nlet b = a;\r\nlet c = 3;\n";
+        let regex = Regex::new(r"let (\w)").unwrap();
+        let mut hunks = hunks("lib.rs", text.lines(), &regex, "const ${1}", true);
+        assert_eq!(hunks.len(), 3);
+        assert_eq!(hunks[1].after, "const b = a;");
+        assert_eq!(
+            super::hunks("lib.rs", text.lines(), &regex, "$1", false)[0].after,
+            "$1 = 1;"
+        );
+
+        hunks[0].enabled = false;
+        hunks[2].before = String::from("changed since");
+        let enabled: Vec<&Hunk> = hunks.iter().filter(|hunk| hunk.enabled).collect();
+        assert_eq!(
+            apply(text, &enabled),
+            (
+                String::from("let a = 1;\r\nconst b = a;\r\nlet c = 3;\n"),
+                1
+            )
+        );
+    }
+}
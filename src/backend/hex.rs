@@ -0,0 +1,145 @@
+use super::cursor::Cursor;
+use std::fmt::Write;
+
+pub const BYTES_PER_ROW: usize = 16;
+// the columns taken up by the offset at the start of each row
+const OFFSET_WIDTH: usize = 10;
+// the column the ascii view of a row starts at
+const ASCII_START: usize = OFFSET_WIDTH + BYTES_PER_ROW * 3 + 1;
+
+/// The raw bytes of a buffer that is being edited in hex, without any decoding into lines.
+///
+/// Positions in the bytes are counted in nibbles, and the nibble after the last byte is where
+/// new bytes get appended.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Hex {
+    pub bytes: Vec<u8>,
+    /// Whether typing at the start of a byte inserts a new one instead of overwriting it
+    pub insert: bool,
+}
+
+impl Hex {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Hex {
+            bytes,
+            insert: false,
+        }
+    }
+
+    /// The last position a cursor can be at
+    pub fn end(&self) -> usize {
+        self.bytes.len() * 2
+    }
+
+    pub fn rows(&self) -> usize {
+        self.bytes.len() / BYTES_PER_ROW + 1
+    }
+
+    /// Draw a row as its offset, the hex of its bytes and their ascii
+    pub fn render_row(&self, row: usize) -> Option<String> {
+        if row >= self.rows() {
+            return None;
+        }
+        let start = row * BYTES_PER_ROW;
+        let bytes = &self.bytes[start..(start + BYTES_PER_ROW).min(self.bytes.len())];
+        let mut text = format!("{start:08x}  ");
+        for i in 0..BYTES_PER_ROW {
+            match bytes.get(i) {
+                Some(byte) => write!(text, "{byte:02x} ").unwrap(),
+                None => text.push_str("   "),
+            }
+        }
+        text.push('|');
+        text.extend(bytes.iter().map(|byte| {
+            if byte.is_ascii_graphic() || *byte == b' ' {
+                char::from(*byte)
+            } else {
+                '.'
+            }
+        }));
+        text.push('|');
+        Some(text)
+    }
+
+    /// Set the nibble at a position to a hex digit, returning the position after it. In insert
+    /// mode typing at the start of a byte makes a new byte for it.
+    pub fn type_digit(&mut self, nibble: usize, digit: u8) -> usize {
+        debug_assert!(digit < 16 && nibble <= self.end());
+        let idx = nibble / 2;
+        let high = nibble.is_multiple_of(2);
+        if idx == self.bytes.len() {
+            self.bytes.push(0);
+        } else if self.insert && high {
+            self.bytes.insert(idx, 0);
+        }
+        let byte = &mut self.bytes[idx];
+        *byte = if high {
+            (*byte & 0x0f) | (digit << 4)
+        } else {
+            (*byte & 0xf0) | digit
+        };
+        nibble + 1
+    }
+
+    /// Remove the byte before the one at a position, returning where the position moves to
+    pub fn backspace(&mut self, nibble: usize) -> usize {
+        if nibble < 2 {
+            return nibble;
+        }
+        self.bytes.remove(nibble / 2 - 1);
+        nibble - 2
+    }
+}
+
+/// The position in nibbles a cursor in a hex buffer is at
+pub fn nibble(cursor: Cursor) -> usize {
+    cursor.row * BYTES_PER_ROW * 2 + cursor.col
+}
+
+/// The cursor for a position in nibbles
+pub fn cursor(nibble: usize) -> Cursor {
+    Cursor {
+        row: nibble / (BYTES_PER_ROW * 2),
+        col: nibble % (BYTES_PER_ROW * 2),
+    }
+}
+
+/// The column a cursor is drawn at in its row
+pub fn screen_col(cursor: Cursor) -> usize {
+    OFFSET_WIDTH + cursor.col / 2 * 3 + cursor.col % 2
+}
+
+/// The column of the ascii view of the byte a cursor is on
+pub fn ascii_col(cursor: Cursor) -> usize {
+    ASCII_START + cursor.col / 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_nibbles() {
+        let mut hex = Hex::new(vec![0x12, 0x34]);
+        assert_eq!(hex.type_digit(1, 0xf), 2);
+        assert_eq!(hex.type_digit(4, 0xa), 5);
+        assert_eq!(hex.bytes, vec![0x1f, 0x34, 0xa0]);
+        hex.insert = true;
+        hex.type_digit(2, 0x5);
+        hex.type_digit(3, 0x6);
+        assert_eq!(hex.bytes, vec![0x1f, 0x56, 0x34, 0xa0]);
+        assert_eq!(hex.backspace(4), 2);
+        assert_eq!(hex.bytes, vec![0x1f, 0x34, 0xa0]);
+    }
+
+    #[test]
+    fn render() {
+        let hex = Hex::new(b"hi\x00there, this i17".to_vec());
+        assert_eq!(hex.rows(), 2);
+        let row = hex.render_row(1).unwrap();
+        assert!(row.starts_with("00000010  31 37 "));
+        assert!(row.ends_with("|17|"));
+        assert!(hex.render_row(0).unwrap().ends_with("|hi.there, this i|"));
+        assert_eq!(ascii_col(cursor(3)), row.find('|').unwrap() + 2);
+    }
+}
@@ -0,0 +1,635 @@
+use super::cursor::{Cursor, Offset};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use toml::{de, value::Table, Value};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A single primitive change to the lines of a buffer.
+///
+/// Every edit knows how to produce its own inverse, so undoing a change is just applying the
+/// inverses of its edits in reverse order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edit {
+    /// insert text (which doesn't contain a newline) before the grapheme at col
+    Insert {
+        row: usize,
+        col: usize,
+        text: String,
+    },
+    /// remove the graphemes of text, starting at col
+    Delete {
+        row: usize,
+        col: usize,
+        text: String,
+    },
+    /// split a line in two at col
+    Split { row: usize, col: usize },
+    /// join the line below row onto row, which was col graphemes long
+    Join { row: usize, col: usize },
+    /// insert a whole new line at row
+    InsertLine { row: usize, text: String },
+    /// remove the whole line at row
+    RemoveLine { row: usize, text: String },
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Edit {
+        match self.clone() {
+            Edit::Insert { row, col, text } => Edit::Delete { row, col, text },
+            Edit::Delete { row, col, text } => Edit::Insert { row, col, text },
+            Edit::Split { row, col } => Edit::Join { row, col },
+            Edit::Join { row, col } => Edit::Split { row, col },
+            Edit::InsertLine { row, text } => Edit::RemoveLine { row, text },
+            Edit::RemoveLine { row, text } => Edit::InsertLine { row, text },
+        }
+    }
+
+    // try to fold a following edit into this one, which is how consecutive typing and
+    // backspacing end up as a single step
+    fn coalesce(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (
+                Edit::Insert { row, col, text },
+                Edit::Insert {
+                    row: next_row,
+                    col: next_col,
+                    text: next_text,
+                },
+            ) if row == next_row && *col + text.graphemes(true).count() == *next_col => {
+                text.push_str(next_text);
+                true
+            }
+            (
+                Edit::Delete { row, col, text },
+                Edit::Delete {
+                    row: next_row,
+                    col: next_col,
+                    text: next_text,
+                },
+            ) if row == next_row && *next_col + next_text.graphemes(true).count() == *col => {
+                text.insert_str(0, next_text);
+                *col = *next_col;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Edit::Insert { text, .. } => format!("insert {text:?}"),
+            Edit::Delete { text, .. } => format!("delete {text:?}"),
+            Edit::Split { .. } => String::from("newline"),
+            Edit::Join { .. } => String::from("join lines"),
+            Edit::InsertLine { text, .. } => format!("add line {text:?}"),
+            Edit::RemoveLine { text, .. } => format!("remove line {text:?}"),
+        }
+    }
+}
+
+/// Where a pane was looking when a step was made, so undoing can put the cursor back
+#[derive(Copy, Clone, Debug, Default)]
+pub struct View {
+    pub cursor: Cursor,
+    pub offset: Offset,
+}
+
+/// A group of edits that are undone and redone together
+#[derive(Clone, Debug, Default)]
+pub struct Step {
+    pub edits: Vec<Edit>,
+    pub before: View,
+    pub after: View,
+}
+
+#[derive(Clone, Debug)]
+struct Revision {
+    parent: usize,
+    children: Vec<usize>,
+    // the child that redo moves to: the one most recently undone out of or created
+    redo: Option<usize>,
+    step: Step,
+    // when the step was last added to
+    time: SystemTime,
+}
+
+/// The undo tree of a buffer.
+///
+/// Every step is a revision whose parent is the revision it was made on top of, so undoing and
+/// then making a new edit starts a new branch instead of throwing the undone steps away.
+/// Revision 0 is the root: the buffer as it was loaded.
+#[derive(Clone, Debug)]
+pub struct History {
+    revisions: Vec<Revision>,
+    current: usize,
+    // whether the current revision can still have edits folded into it
+    open: bool,
+    // the revision that was current when the buffer was last saved
+    saved: Option<usize>,
+    view: View,
+    // while above 0, every edit joins the current revision so that a compound operation is
+    // undone in one step
+    group_depth: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            revisions: vec![Revision {
+                parent: 0,
+                children: Vec::new(),
+                redo: None,
+                step: Step::default(),
+                time: SystemTime::now(),
+            }],
+            current: 0,
+            open: false,
+            saved: Some(0),
+            view: View::default(),
+            group_depth: 0,
+        }
+    }
+}
+
+impl History {
+    /// Record where the pane editing the buffer is looking.
+    ///
+    /// This should be called before and after every edit: the view before an edit becomes the
+    /// view restored by undo, and the view after becomes the one restored by redo.
+    pub fn set_view(&mut self, view: View) {
+        self.view = view;
+        if self.open {
+            self.revisions[self.current].step.after = view;
+        }
+    }
+
+    pub fn push(&mut self, edit: Edit) {
+        let current = &mut self.revisions[self.current];
+        if self.open && current.children.is_empty() {
+            if let Some(last) = current.step.edits.last_mut() {
+                if last.coalesce(&edit) {
+                    current.time = SystemTime::now();
+                    return;
+                }
+            }
+            if self.group_depth > 0 {
+                current.step.edits.push(edit);
+                current.time = SystemTime::now();
+                return;
+            }
+        }
+        let id = self.revisions.len();
+        let current = &mut self.revisions[self.current];
+        current.children.push(id);
+        current.redo = Some(id);
+        self.revisions.push(Revision {
+            parent: self.current,
+            children: Vec::new(),
+            redo: None,
+            step: Step {
+                edits: vec![edit],
+                before: self.view,
+                after: self.view,
+            },
+            time: SystemTime::now(),
+        });
+        self.current = id;
+        self.open = true;
+    }
+
+    /// Start grouping every following edit into one revision, until the matching `end_group`
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.open = false;
+        }
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            self.open = false;
+        }
+    }
+
+    /// Move to the parent of the current revision, returning the step that has to be reversed
+    pub fn undo(&mut self) -> Option<Step> {
+        self.open = false;
+        if self.current == 0 {
+            return None;
+        }
+        let child = self.current;
+        self.current = self.revisions[child].parent;
+        self.revisions[self.current].redo = Some(child);
+        Some(self.revisions[child].step.clone())
+    }
+
+    /// Move to the most recently visited child of the current revision, returning the step that
+    /// has to be applied
+    pub fn redo(&mut self) -> Option<Step> {
+        self.open = false;
+        let current = &self.revisions[self.current];
+        let child = current.redo.or_else(|| current.children.last().copied())?;
+        self.current = child;
+        Some(self.revisions[child].step.clone())
+    }
+
+    /// Move to any revision in the tree, returning the edits that have to be applied (in order)
+    /// to get there and the view to restore once they are.
+    pub fn goto(&mut self, target: usize) -> Option<(Vec<Edit>, View)> {
+        if target >= self.revisions.len() {
+            return None;
+        }
+        self.open = false;
+        let ancestors = |mut rev: usize| {
+            let mut path = vec![rev];
+            while rev != 0 {
+                rev = self.revisions[rev].parent;
+                path.push(rev);
+            }
+            path
+        };
+        let up = ancestors(self.current);
+        let mut down = ancestors(target);
+        // strip the common ancestors so that only the revisions to undo and redo are left
+        let common = up
+            .iter()
+            .rev()
+            .zip(down.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let up = &up[..up.len() - common];
+        down.truncate(down.len() - common);
+        down.reverse();
+
+        let mut edits = Vec::new();
+        let mut view = self.view;
+        for rev in up {
+            let step = &self.revisions[*rev].step;
+            edits.extend(step.edits.iter().rev().map(Edit::inverse));
+            view = step.before;
+        }
+        for rev in &down {
+            let step = &self.revisions[*rev].step;
+            edits.extend(step.edits.iter().cloned());
+            view = step.after;
+        }
+        for rev in up.iter().chain(down.iter()) {
+            let parent = self.revisions[*rev].parent;
+            self.revisions[parent].redo = Some(*rev);
+        }
+        self.current = target;
+        Some((edits, view))
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// The newest revision that had already been made at the given time
+    pub fn at_time(&self, time: SystemTime) -> usize {
+        self.revisions
+            .iter()
+            .rposition(|rev| rev.time <= time)
+            .unwrap_or(0)
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.open = false;
+        self.saved = Some(self.current);
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.current)
+    }
+
+    /// The edits that, applied from the current revision, pass through every other revision in
+    /// the tree and come back again
+    pub fn tour(&self) -> Vec<Edit> {
+        let mut edits = Vec::new();
+        // moves between neighbouring revisions, and whether they head back the way the tour came
+        let mut stack = vec![(self.current, self.current, false)];
+        while let Some((from, to, back)) = stack.pop() {
+            if from != to {
+                if to != 0 && self.revisions[to].parent == from {
+                    edits.extend(self.revisions[to].step.edits.iter().cloned());
+                } else {
+                    let step = &self.revisions[from].step;
+                    edits.extend(step.edits.iter().rev().map(Edit::inverse));
+                }
+            }
+            if back {
+                continue;
+            }
+            if from != to {
+                stack.push((to, from, true));
+            }
+            let rev = &self.revisions[to];
+            let parent = (to != 0).then_some(rev.parent);
+            for next in rev.children.iter().copied().chain(parent) {
+                if next != from {
+                    stack.push((to, next, false));
+                }
+            }
+        }
+        edits
+    }
+
+    /// Draw the tree as one line of text per revision, paired with the id of the revision.
+    ///
+    /// The newest child of a revision continues straight down, while older branches are indented
+    /// and drawn above it.
+    pub fn render(&self) -> Vec<(usize, String)> {
+        let now = SystemTime::now();
+        let mut lines = Vec::with_capacity(self.revisions.len());
+        let mut stack = vec![(0, 0)];
+        while let Some((id, indent)) = stack.pop() {
+            let rev = &self.revisions[id];
+            let summary = match rev.step.edits.len() {
+                0 => String::from("original"),
+                1 => rev.step.edits[0].describe(),
+                n => format!("{n} edits"),
+            };
+            lines.push((
+                id,
+                format!(
+                    "{}{} {:>4} {:>8}  {}{}",
+                    "| ".repeat(indent),
+                    if id == self.current { '*' } else { 'o' },
+                    id,
+                    ago(now.duration_since(rev.time).unwrap_or_default()),
+                    summary,
+                    if self.saved == Some(id) {
+                        " (saved)"
+                    } else {
+                        ""
+                    },
+                ),
+            ));
+            if let Some((newest, older)) = rev.children.split_last() {
+                stack.push((*newest, indent));
+                for child in older.iter().rev() {
+                    stack.push((*child, indent + 1));
+                }
+            }
+        }
+        lines
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Toml(de::Error),
+    MissingKey(&'static str),
+    WrongType(&'static str),
+    UnknownEdit(String),
+    BadRevision(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Toml(e) => write!(f, "{e}"),
+            Error::MissingKey(key) => write!(f, "missing the key {key}"),
+            Error::WrongType(key) => write!(f, "the key {key} has the wrong type"),
+            Error::UnknownEdit(kind) => write!(f, "unknown kind of edit {kind}"),
+            Error::BadRevision(rev) => write!(f, "revision {rev} is out of place"),
+        }
+    }
+}
+
+fn int(n: usize) -> Value {
+    Value::Integer(i64::try_from(n).unwrap_or(i64::MAX))
+}
+
+fn get<'a>(t: &'a Table, key: &'static str) -> Result<&'a Value, Error> {
+    t.get(key).ok_or(Error::MissingKey(key))
+}
+
+fn get_int(t: &Table, key: &'static str) -> Result<usize, Error> {
+    get(t, key)?
+        .as_integer()
+        .and_then(|i| usize::try_from(i).ok())
+        .ok_or(Error::WrongType(key))
+}
+
+fn get_str(t: &Table, key: &'static str) -> Result<String, Error> {
+    get(t, key)?
+        .as_str()
+        .map(ToString::to_string)
+        .ok_or(Error::WrongType(key))
+}
+
+impl View {
+    fn to_toml(self) -> Value {
+        Value::Array(vec![
+            int(self.cursor.row),
+            int(self.cursor.col),
+            int(self.offset.row),
+            int(self.offset.col),
+        ])
+    }
+
+    fn from_toml(v: &Value, key: &'static str) -> Result<Self, Error> {
+        let nums = v
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(Value::as_integer)
+                    .filter_map(|i| usize::try_from(i).ok())
+                    .collect::<Vec<_>>()
+            })
+            .ok_or(Error::WrongType(key))?;
+        if let [row, col, offset_row, offset_col] = nums[..] {
+            Ok(View {
+                cursor: Cursor { row, col },
+                offset: Offset {
+                    row: offset_row,
+                    col: offset_col,
+                },
+            })
+        } else {
+            Err(Error::WrongType(key))
+        }
+    }
+}
+
+impl Edit {
+    fn to_toml(&self) -> Value {
+        let mut t = Table::new();
+        let (kind, row, col, text) = match self {
+            Edit::Insert { row, col, text } => ("insert", row, Some(col), Some(text)),
+            Edit::Delete { row, col, text } => ("delete", row, Some(col), Some(text)),
+            Edit::Split { row, col } => ("split", row, Some(col), None),
+            Edit::Join { row, col } => ("join", row, Some(col), None),
+            Edit::InsertLine { row, text } => ("insert_line", row, None, Some(text)),
+            Edit::RemoveLine { row, text } => ("remove_line", row, None, Some(text)),
+        };
+        t.insert(String::from("kind"), Value::String(kind.to_string()));
+        t.insert(String::from("row"), int(*row));
+        if let Some(col) = col {
+            t.insert(String::from("col"), int(*col));
+        }
+        if let Some(text) = text {
+            t.insert(String::from("text"), Value::String(text.clone()));
+        }
+        Value::Table(t)
+    }
+
+    fn from_toml(v: &Value) -> Result<Self, Error> {
+        let t = v.as_table().ok_or(Error::WrongType("edits"))?;
+        let row = get_int(t, "row")?;
+        Ok(match &get_str(t, "kind")?[..] {
+            "insert" => Edit::Insert {
+                row,
+                col: get_int(t, "col")?,
+                text: get_str(t, "text")?,
+            },
+            "delete" => Edit::Delete {
+                row,
+                col: get_int(t, "col")?,
+                text: get_str(t, "text")?,
+            },
+            "split" => Edit::Split {
+                row,
+                col: get_int(t, "col")?,
+            },
+            "join" => Edit::Join {
+                row,
+                col: get_int(t, "col")?,
+            },
+            "insert_line" => Edit::InsertLine {
+                row,
+                text: get_str(t, "text")?,
+            },
+            "remove_line" => Edit::RemoveLine {
+                row,
+                text: get_str(t, "text")?,
+            },
+            kind => return Err(Error::UnknownEdit(kind.to_string())),
+        })
+    }
+}
+
+impl History {
+    /// Serialize the tree, along with which revisions are current and saved, as toml
+    pub fn to_toml(&self) -> String {
+        let revisions = self
+            .revisions
+            .iter()
+            .map(|rev| {
+                let mut t = Table::new();
+                t.insert(String::from("parent"), int(rev.parent));
+                if let Some(redo) = rev.redo {
+                    t.insert(String::from("redo"), int(redo));
+                }
+                let secs = rev
+                    .time
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                t.insert(
+                    String::from("time"),
+                    Value::Integer(i64::try_from(secs).unwrap_or(i64::MAX)),
+                );
+                t.insert(String::from("before"), rev.step.before.to_toml());
+                t.insert(String::from("after"), rev.step.after.to_toml());
+                t.insert(
+                    String::from("edits"),
+                    Value::Array(rev.step.edits.iter().map(Edit::to_toml).collect()),
+                );
+                Value::Table(t)
+            })
+            .collect();
+        let mut t = Table::new();
+        t.insert(String::from("current"), int(self.current));
+        if let Some(saved) = self.saved {
+            t.insert(String::from("saved"), int(saved));
+        }
+        t.insert(String::from("revisions"), Value::Array(revisions));
+        Value::Table(t).to_string()
+    }
+}
+
+impl FromStr for History {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let toml = s.parse::<Value>().map_err(Error::Toml)?;
+        let t = toml.as_table().ok_or(Error::WrongType("history"))?;
+        let mut revisions: Vec<Revision> = Vec::new();
+        for v in get(t, "revisions")?
+            .as_array()
+            .ok_or(Error::WrongType("revisions"))?
+        {
+            let rt = v.as_table().ok_or(Error::WrongType("revisions"))?;
+            let id = revisions.len();
+            let parent = get_int(rt, "parent")?;
+            // parents always come before their children, which keeps the tree acyclic
+            if id != 0 {
+                revisions
+                    .get_mut(parent)
+                    .filter(|_| parent < id)
+                    .ok_or(Error::BadRevision(id))?
+                    .children
+                    .push(id);
+            }
+            let time = get_int(rt, "time")?;
+            revisions.push(Revision {
+                parent,
+                children: Vec::new(),
+                redo: rt.get("redo").map(|_| get_int(rt, "redo")).transpose()?,
+                step: Step {
+                    edits: get(rt, "edits")?
+                        .as_array()
+                        .ok_or(Error::WrongType("edits"))?
+                        .iter()
+                        .map(Edit::from_toml)
+                        .collect::<Result<_, _>>()?,
+                    before: View::from_toml(get(rt, "before")?, "before")?,
+                    after: View::from_toml(get(rt, "after")?, "after")?,
+                },
+                time: UNIX_EPOCH + Duration::from_secs(u64::try_from(time).unwrap_or_default()),
+            });
+        }
+        let current = get_int(t, "current")?;
+        let saved = t.get("saved").map(|_| get_int(t, "saved")).transpose()?;
+        for rev in std::iter::once(current).chain(saved) {
+            if rev >= revisions.len() {
+                return Err(Error::BadRevision(rev));
+            }
+        }
+        for (id, rev) in revisions.iter().enumerate() {
+            if rev.redo.is_some_and(|redo| redo >= revisions.len()) {
+                return Err(Error::BadRevision(id));
+            }
+        }
+        if revisions.is_empty() {
+            return Err(Error::MissingKey("revisions"));
+        }
+        let view = revisions[current].step.after;
+        Ok(History {
+            revisions,
+            current,
+            open: false,
+            saved,
+            view,
+            group_depth: 0,
+        })
+    }
+}
+
+fn ago(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{secs}s ago")
+    } else if secs < 60 * 60 {
+        format!("{}m ago", secs / 60)
+    } else if secs < 60 * 60 * 24 {
+        format!("{}h ago", secs / (60 * 60))
+    } else {
+        format!("{}d ago", secs / (60 * 60 * 24))
+    }
+}
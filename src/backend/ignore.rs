@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+// the most files that walk will find, so that opening a huge tree doesn't hang
+const MAX_FILES: usize = 100_000;
+
+/// One line of a .gitignore file
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Pattern {
+    glob: Vec<char>,
+    /// Whether the pattern started with `!`, un-ignoring what it matches
+    negated: bool,
+    /// Whether the pattern ended with `/`, so that it only matches directories
+    dir_only: bool,
+    /// Whether the pattern has a `/` in it, so that it is matched against the path from the
+    /// directory of the .gitignore rather than against the file name at any depth
+    anchored: bool,
+}
+
+impl Pattern {
+    fn parse(line: &str) -> Option<Pattern> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(Pattern {
+            glob: line.chars().collect(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    // `relative` is the path from the directory of the .gitignore, with `/` between components
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let text: Vec<char> = if self.anchored {
+            relative.chars().collect()
+        } else {
+            relative
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .collect()
+        };
+        glob(&self.glob, &text)
+    }
+}
+
+// match text against a gitignore glob, where `*` and `?` don't match `/` but `**` does
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `**/` matches any number of whole directories, including none
+            let rest = rest.strip_prefix(&['/']).unwrap_or(rest);
+            rest.is_empty()
+                || (0..=text.len())
+                    .filter(|idx| *idx == 0 || text[idx - 1] == '/')
+                    .any(|idx| glob(rest, &text[idx..]))
+        }
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|idx| *idx == 0 || text[idx - 1] != '/')
+            .any(|idx| glob(rest, &text[idx..])),
+        ['?', rest @ ..] => match text {
+            [c, text @ ..] if *c != '/' => glob(rest, text),
+            _ => false,
+        },
+        ['[', class @ ..] => match (class_len(class), text) {
+            (Some(len), [c, text @ ..]) => {
+                in_class(&class[..len], *c) && glob(&class[len + 1..], text)
+            }
+            (Some(_), []) => false,
+            // a `[` without a `]` is just a `[`
+            (None, _) => text.first() == Some(&'[') && glob(class, &text[1..]),
+        },
+        ['\\', c, rest @ ..] | [c, rest @ ..] => match text {
+            [first, text @ ..] if first == c => glob(rest, text),
+            _ => false,
+        },
+    }
+}
+
+// how long the inside of a character class is, up to its closing `]`
+fn class_len(class: &[char]) -> Option<usize> {
+    // a `]` straight after the opening `[` (or `[!`) is part of the class
+    let start = match class {
+        ['!' | '^', ']', ..] => 2,
+        ['!' | '^' | ']', ..] => 1,
+        _ => 0,
+    };
+    class[start..]
+        .iter()
+        .position(|c| *c == ']')
+        .map(|idx| idx + start)
+}
+
+fn in_class(class: &[char], c: char) -> bool {
+    let (negated, mut class) = match class {
+        ['!' | '^', rest @ ..] => (true, rest),
+        _ => (false, class),
+    };
+    let mut found = false;
+    while !class.is_empty() {
+        match class {
+            [from, '-', to, rest @ ..] => {
+                found |= (*from..=*to).contains(&c);
+                class = rest;
+            }
+            [member, rest @ ..] => {
+                found |= *member == c;
+                class = rest;
+            }
+            [] => break,
+        }
+    }
+    found != negated
+}
+
+/// Decides which paths under a directory git would ignore, reading the .gitignore files along
+/// the way as they are needed. Paths should be checked from the top down, like when walking the
+/// tree, since the contents of an ignored directory aren't checked again.
+#[derive(Debug)]
+pub struct Ignore {
+    root: PathBuf,
+    // the patterns of the .gitignore in each directory that has been looked at
+    rules: HashMap<PathBuf, Vec<Pattern>>,
+}
+
+impl Ignore {
+    pub fn new(root: &Path) -> Ignore {
+        Ignore {
+            root: root.to_path_buf(),
+            rules: HashMap::new(),
+        }
+    }
+
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        if path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let names: Vec<String> = relative
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        let mut ignored = false;
+        let mut dir = self.root.clone();
+        // deeper .gitignore files come later, so they get the last word
+        for depth in 0..names.len() {
+            let patterns = self
+                .rules
+                .entry(dir.clone())
+                .or_insert_with(|| read(&dir.join(".gitignore")));
+            let relative = names[depth..].join("/");
+            for pattern in patterns.iter() {
+                if pattern.matches(&relative, is_dir) {
+                    ignored = !pattern.negated;
+                }
+            }
+            dir.push(&names[depth]);
+        }
+        ignored
+    }
+}
+
+/// Every file under a directory that git wouldn't ignore, sorted. Symlinks to directories aren't
+/// followed, so that a loop can't trap the walk, and it gives up after `MAX_FILES` files.
+pub fn walk(root: &Path) -> Vec<PathBuf> {
+    let mut ignore = Ignore::new(root);
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(read) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in read.filter_map(Result::ok) {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if ignore.is_ignored(&path, file_type.is_dir()) {
+                continue;
+            }
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if files.len() < MAX_FILES {
+                files.push(path);
+            } else {
+                dirs.clear();
+                break;
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+fn read(path: &Path) -> Vec<Pattern> {
+    fs::read_to_string(path)
+        .map(|text| text.lines().filter_map(Pattern::parse).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::TempDir;
+
+    #[test]
+    fn gitignore_patterns() {
+        let temp = TempDir::new("ignore");
+        let dir = temp.path();
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(
+            dir.join(".gitignore"),
+            "# build output\ntarget/\n*.log\n!keep.log\n/docs/*.md\nsrc/**/gen\n[ab]?.tmp\n",
+        )
+        .unwrap();
+        fs::write(dir.join("src").join(".gitignore"), "local\n!*.log\n").unwrap();
+        let mut ignore = Ignore::new(dir);
+        let mut check = |path: &str, is_dir| ignore.is_ignored(&dir.join(path), is_dir);
+
+        assert!(check("target", true));
+        assert!(!check("target", false));
+        assert!(check("debug.log", false));
+        assert!(!check("keep.log", false));
+        assert!(check("docs/readme.md", false));
+        assert!(!check("docs/nested/readme.md", false));
+        assert!(check("src/gen", true));
+        assert!(check("src/deep/er/gen", false));
+        assert!(check("a1.tmp", false));
+        assert!(!check("c1.tmp", false));
+        assert!(check("src/local", false));
+        assert!(!check("src/debug.log", false));
+        assert!(check(".git", true));
+        assert!(!check("src/main.rs", false));
+
+        for file in [
+            "target/debug",
+            "src/main.rs",
+            "src/gen",
+            "debug.log",
+            "keep.log",
+        ] {
+            fs::create_dir_all(dir.join(file).parent().unwrap()).unwrap();
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let names: Vec<PathBuf> = walk(dir)
+            .into_iter()
+            .map(|path| path.strip_prefix(dir).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            names,
+            [".gitignore", "keep.log", "src/.gitignore", "src/main.rs"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        );
+    }
+}
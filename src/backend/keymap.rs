@@ -0,0 +1,554 @@
+use super::command::{Binding, Command, IsNotCommand};
+use super::dirs;
+use super::editor::Mode;
+use crate::frontend::ui::{EscapeSeq, Event};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str;
+use toml::{de, Value};
+
+// the keys purport has always had, which work in every mode
+const DEFAULT: &str = "
+preset = \"none\"
+mode = \"insert\"
+
+[global]
+\"Up\" = \"move-up\"
+\"Down\" = \"move-down\"
+\"Left\" = \"move-left\"
+\"Right\" = \"move-right\"
+\"Shift-Up\" = \"select-up\"
+\"Shift-Down\" = \"select-down\"
+\"Shift-Left\" = \"select-left\"
+\"Shift-Right\" = \"select-right\"
+\"Alt-o\" = \"open-file\"
+\"Alt-w\" = \"close-buffer\"
+\"Alt-b\" = \"list-buffers\"
+\"Alt-n\" = \"next-buffer\"
+\"Alt-p\" = \"previous-buffer\"
+\"Alt-f\" = \"find-file\"
+\"Alt-g\" = \"search-project\"
+\"Alt-%\" = \"replace-project\"
+\"Alt-e\" = \"toggle-explorer\"
+\"Alt-t\" = \"new-tab\"
+\"Alt-q\" = \"close-tab\"
+\"Alt-r\" = \"rename-tab\"
+\"Alt-.\" = \"next-tab\"
+\"Alt-,\" = \"previous-tab\"
+\"Alt-{\" = \"move-tab-left\"
+\"Alt-}\" = \"move-tab-right\"
+\"Alt-1\" = \"goto-tab 1\"
+\"Alt-2\" = \"goto-tab 2\"
+\"Alt-3\" = \"goto-tab 3\"
+\"Alt-4\" = \"goto-tab 4\"
+\"Alt-5\" = \"goto-tab 5\"
+\"Alt-6\" = \"goto-tab 6\"
+\"Alt-7\" = \"goto-tab 7\"
+\"Alt-8\" = \"goto-tab 8\"
+\"Alt-9\" = \"goto-tab 9\"
+\"Alt-s\" = \"split-below\"
+\"Alt-v\" = \"split-right\"
+\"Alt-c\" = \"close-pane\"
+\"Alt-h\" = \"focus-left\"
+\"Alt-j\" = \"focus-down\"
+\"Alt-k\" = \"focus-up\"
+\"Alt-l\" = \"focus-right\"
+\"Alt-+\" = \"grow-height\"
+\"Alt--\" = \"shrink-height\"
+\"Alt->\" = \"grow-width\"
+\"Alt-<\" = \"shrink-width\"
+\"Alt-x\" = \"command-palette\"
+\"Ctrl-A\" = \"insert-text\"
+\"Ctrl-Space\" = \"toggle-selection\"
+\"Ctrl-C\" = \"copy\"
+\"Ctrl-X\" = \"cut\"
+\"Ctrl-V\" = \"paste\"
+\"Ctrl-N\" = \"add-cursor-below\"
+\"Ctrl-D\" = \"add-cursor-at-next-match\"
+\"Ctrl-G\" = \"cancel\"
+\"Ctrl-F\" = \"search\"
+\"Ctrl-E\" = \"replace\"
+\"Ctrl-B\" = \"convert-format\"
+\"Ctrl-O\" = \"set-fallback-encoding\"
+\"Ctrl-K\" = \"toggle-hex\"
+\"Ctrl-W\" = \"set-backup\"
+\"Ctrl-T\" = \"toggle-hex-insert\"
+\"Ctrl-L\" = \"search-next\"
+\"Ctrl-P\" = \"search-previous\"
+\"Ctrl-R\" = \"choose-register\"
+\"Ctrl-Q\" = \"quit\"
+\"Ctrl-S\" = \"save\"
+\"Ctrl-Z\" = \"undo\"
+\"Ctrl-Y\" = \"redo\"
+\"Ctrl-U\" = \"browse-history\"
+
+[insert]
+\"Backspace\" = \"backspace\"
+\"Esc\" = \"normal-mode\"
+";
+
+// emacs-like keys, without any modes
+const EMACS: &str = "
+preset = \"none\"
+mode = \"insert\"
+
+[global]
+\"Up\" = \"move-up\"
+\"Down\" = \"move-down\"
+\"Left\" = \"move-left\"
+\"Right\" = \"move-right\"
+\"Shift-Up\" = \"select-up\"
+\"Shift-Down\" = \"select-down\"
+\"Shift-Left\" = \"select-left\"
+\"Shift-Right\" = \"select-right\"
+\"Ctrl-P\" = \"move-up\"
+\"Ctrl-N\" = \"move-down\"
+\"Ctrl-B\" = \"move-left\"
+\"Ctrl-F\" = \"move-right\"
+\"Ctrl-Space\" = \"toggle-selection\"
+\"Alt-w\" = \"copy\"
+\"Ctrl-W\" = \"cut\"
+\"Ctrl-Y\" = \"paste\"
+\"Ctrl-G\" = \"cancel\"
+\"Ctrl-/\" = \"undo\"
+\"Ctrl-S\" = \"search\"
+\"Ctrl-R\" = \"search-previous\"
+\"Alt-%\" = \"replace\"
+\"Ctrl-X Ctrl-F\" = \"open-file\"
+\"Ctrl-X Ctrl-S\" = \"save\"
+\"Ctrl-X Ctrl-C\" = \"quit\"
+\"Ctrl-X u\" = \"undo\"
+\"Ctrl-X b\" = \"list-buffers\"
+\"Ctrl-X k\" = \"close-buffer\"
+\"Ctrl-X Left\" = \"previous-buffer\"
+\"Ctrl-X Right\" = \"next-buffer\"
+\"Ctrl-X r\" = \"choose-register\"
+\"Ctrl-X d\" = \"toggle-explorer\"
+\"Ctrl-X 2\" = \"split-below\"
+\"Ctrl-X 3\" = \"split-right\"
+\"Ctrl-X 0\" = \"close-pane\"
+\"Ctrl-X o\" = \"focus-right\"
+\"Ctrl-X t 2\" = \"new-tab\"
+\"Ctrl-X t 0\" = \"close-tab\"
+\"Ctrl-X t o\" = \"next-tab\"
+\"Ctrl-X t O\" = \"previous-tab\"
+\"Ctrl-X t r\" = \"rename-tab\"
+\"Alt-x\" = \"command-palette\"
+
+[insert]
+\"Backspace\" = \"backspace\"
+";
+
+// vi-like keys, starting in Normal mode, on top of the default ones
+const VI: &str = "
+preset = \"default\"
+mode = \"normal\"
+
+[normal]
+\":\" = \"command-palette\"
+\"Ctrl-R\" = \"redo\"
+\"/\" = \"search\"
+\"n\" = \"search-next\"
+\"N\" = \"search-previous\"
+\"Ctrl-W s\" = \"split-below\"
+\"Ctrl-W v\" = \"split-right\"
+\"Ctrl-W c\" = \"close-pane\"
+\"Ctrl-W h\" = \"focus-left\"
+\"Ctrl-W j\" = \"focus-down\"
+\"Ctrl-W k\" = \"focus-up\"
+\"Ctrl-W l\" = \"focus-right\"
+\"g t\" = \"next-tab\"
+\"g T\" = \"previous-tab\"
+";
+
+/// The directory that purport reads its configuration from, such as the keymap
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// What some keys do in a mode
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Lookup<'a> {
+    Bound(&'a Binding),
+    /// The keys are the start of a chord, so there are more to come
+    Prefix,
+    Unbound,
+}
+
+// the tables of a keymap, where the global one has the keys for every mode that the table of the
+// mode doesn't bind
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Table {
+    Global,
+    Insert,
+    Normal,
+    Visual,
+}
+
+impl Table {
+    fn of(mode: Mode) -> Table {
+        match mode {
+            Mode::Insert => Table::Insert,
+            Mode::Normal => Table::Normal,
+            Mode::Visual | Mode::VisualLine => Table::Visual,
+        }
+    }
+}
+
+/// Which commands keys run in each mode.
+///
+/// A keymap is read from toml with a table of bindings for each mode, `insert`, `normal` and
+/// `visual`, as well as a `global` one for every mode. Bindings map keys like `"Ctrl-S"`, or
+/// chords of them separated by spaces like `"Ctrl-X Ctrl-S"`, to the name of a command, which can
+/// be followed by an argument such as in `"goto-tab 2"`. Binding keys to `""` unbinds them.
+/// `preset` names the keymap it starts from, `default`, `emacs`, `vi` or `none`, with the default
+/// one being `default`, and `mode` is the mode the editor starts in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Keymap {
+    /// The mode the editor starts in
+    pub start: Mode,
+    tables: HashMap<Table, HashMap<Vec<Event>, Binding>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Toml(de::Error),
+    IO(io::Error),
+    Command(IsNotCommand),
+    IsntTable(Value),
+    IsntString(Value),
+    UnknownKey(String),
+    UnknownMode(String),
+    UnknownPreset(String),
+    IllegalKey(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Toml(e) => write!(f, "{e}"),
+            Error::IO(e) => write!(f, "{e}"),
+            Error::Command(e) => write!(f, "{e}"),
+            Error::IsntTable(v) => write!(f, "expected a table but found {v}"),
+            Error::IsntString(v) => write!(f, "expected a string but found {v}"),
+            Error::UnknownKey(keys) => write!(f, "{keys} aren't keys that can be bound"),
+            Error::UnknownMode(mode) => write!(f, "the editor can't start in {mode} mode"),
+            Error::UnknownPreset(name) => write!(f, "there is no preset keymap called {name}"),
+            Error::IllegalKey(k) => write!(f, "{k} isn't part of a keymap"),
+        }
+    }
+}
+
+impl Keymap {
+    /// One of the keymaps that come with purport, by name
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let text = match name {
+            "default" => DEFAULT,
+            "emacs" => EMACS,
+            "vi" => VI,
+            _ => return None,
+        };
+        Some(text.parse().unwrap())
+    }
+
+    /// Read the keymap from keymap.toml in the config directory, or use the default one if
+    /// there isn't one
+    pub fn load() -> Result<Keymap, Error> {
+        let Some(path) = config_dir().map(|dir| dir.join("keymap.toml")) else {
+            return Ok(Keymap::default());
+        };
+        match fs::read_to_string(path) {
+            Ok(text) => text.parse(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Keymap::default()),
+            Err(e) => Err(Error::IO(e)),
+        }
+    }
+
+    /// The shortest keys that run a command without an argument in a mode, if there are any
+    pub fn keys_for(&self, mode: Mode, command: Command) -> Option<Vec<Event>> {
+        [Table::of(mode), Table::Global]
+            .iter()
+            .filter_map(|table| self.tables.get(table))
+            .flat_map(HashMap::keys)
+            .filter(|keys| match self.lookup(mode, keys) {
+                // keys in the global table can be hidden by the table of the mode
+                Lookup::Bound(binding) => binding.command == command && binding.arg.is_none(),
+                Lookup::Prefix | Lookup::Unbound => false,
+            })
+            .min_by_key(|keys| (keys.len(), chord_name(keys)))
+            .cloned()
+    }
+
+    /// What some keys do in a mode, going by the table of the mode before the global one
+    pub fn lookup(&self, mode: Mode, keys: &[Event]) -> Lookup<'_> {
+        for table in [Table::of(mode), Table::Global] {
+            let Some(bindings) = self.tables.get(&table) else {
+                continue;
+            };
+            if let Some(binding) = bindings.get(keys) {
+                return Lookup::Bound(binding);
+            } else if bindings
+                .keys()
+                .any(|chord| chord.len() > keys.len() && chord.starts_with(keys))
+            {
+                return Lookup::Prefix;
+            }
+        }
+        Lookup::Unbound
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::preset("default").unwrap()
+    }
+}
+
+impl str::FromStr for Keymap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let toml = s.parse::<Value>().map_err(Error::Toml)?;
+        let Value::Table(t) = toml else {
+            return Err(Error::IsntTable(toml));
+        };
+        let mut keymap = match t.get("preset") {
+            None => Keymap::default(),
+            Some(Value::String(name)) if name == "none" => Keymap {
+                start: Mode::Insert,
+                tables: HashMap::new(),
+            },
+            Some(Value::String(name)) => {
+                Keymap::preset(name).ok_or_else(|| Error::UnknownPreset(name.clone()))?
+            }
+            Some(v) => return Err(Error::IsntString(v.clone())),
+        };
+        for (k, v) in t {
+            let table = match &k[..] {
+                "preset" => continue,
+                "mode" => {
+                    keymap.start = match v.as_str() {
+                        Some("insert") => Mode::Insert,
+                        Some("normal") => Mode::Normal,
+                        Some(mode) => return Err(Error::UnknownMode(mode.to_string())),
+                        None => return Err(Error::IsntString(v)),
+                    };
+                    continue;
+                }
+                "global" => Table::Global,
+                "insert" => Table::Insert,
+                "normal" => Table::Normal,
+                "visual" => Table::Visual,
+                k => return Err(Error::IllegalKey(k.to_string())),
+            };
+            let Value::Table(bindings) = v else {
+                return Err(Error::IsntTable(v));
+            };
+            let table = keymap.tables.entry(table).or_default();
+            for (keys, binding) in bindings {
+                let chord = parse_chord(&keys).ok_or(Error::UnknownKey(keys))?;
+                let Value::String(binding) = binding else {
+                    return Err(Error::IsntString(binding));
+                };
+                if binding.is_empty() {
+                    table.remove(&chord);
+                } else {
+                    table.insert(chord, binding.parse().map_err(Error::Command)?);
+                }
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+/// Parse keys separated by spaces, like `Ctrl-X Ctrl-S`
+pub fn parse_chord(s: &str) -> Option<Vec<Event>> {
+    let chord: Option<Vec<Event>> = s.split_whitespace().map(parse_key).collect();
+    chord.filter(|chord| !chord.is_empty())
+}
+
+/// Parse a key like `Ctrl-S`, `Alt-x`, `Shift-Up`, `Enter` or `a`
+pub fn parse_key(s: &str) -> Option<Event> {
+    let special = |seq| Some(Event::SpecialChar(seq));
+    let normal = |c| Some(Event::NormalChar(c));
+    match s {
+        "Up" => special(EscapeSeq::UpArrow),
+        "Down" => special(EscapeSeq::DownArrow),
+        "Left" => special(EscapeSeq::LeftArrow),
+        "Right" => special(EscapeSeq::RightArrow),
+        "Shift-Up" => special(EscapeSeq::ShiftUpArrow),
+        "Shift-Down" => special(EscapeSeq::ShiftDownArrow),
+        "Shift-Left" => special(EscapeSeq::ShiftLeftArrow),
+        "Shift-Right" => special(EscapeSeq::ShiftRightArrow),
+        "Esc" => special(EscapeSeq::Escape),
+        "Enter" => normal('\r'),
+        "Tab" => normal('\t'),
+        "Backspace" => normal('\x7f'),
+        "Space" => normal(' '),
+        "Ctrl-Space" => normal('\0'),
+        "Ctrl-/" => normal('\x1f'),
+        _ => {
+            if let Some(key) = s.strip_prefix("Alt-") {
+                single(key).map(|c| Event::SpecialChar(EscapeSeq::Alt(c)))
+            } else if let Some(key) = s.strip_prefix("Ctrl-") {
+                // terminals send control keys as the character with bit 6 cleared
+                let c = single(key)?.to_ascii_uppercase();
+                ('@'..='_')
+                    .contains(&c)
+                    .then(|| char::from_u32(u32::from(c) ^ 0x40))
+                    .flatten()
+                    .map(Event::NormalChar)
+            } else {
+                single(s).map(Event::NormalChar)
+            }
+        }
+    }
+}
+
+// the character that a string is made of, if it is just one
+fn single(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    let c = chars.next()?;
+    chars.next().is_none().then_some(c)
+}
+
+/// The name of a key, the way that `parse_key` reads it
+pub fn key_name(key: &Event) -> String {
+    let name = match key {
+        Event::SpecialChar(EscapeSeq::UpArrow) => "Up",
+        Event::SpecialChar(EscapeSeq::DownArrow) => "Down",
+        Event::SpecialChar(EscapeSeq::LeftArrow) => "Left",
+        Event::SpecialChar(EscapeSeq::RightArrow) => "Right",
+        Event::SpecialChar(EscapeSeq::ShiftUpArrow) => "Shift-Up",
+        Event::SpecialChar(EscapeSeq::ShiftDownArrow) => "Shift-Down",
+        Event::SpecialChar(EscapeSeq::ShiftLeftArrow) => "Shift-Left",
+        Event::SpecialChar(EscapeSeq::ShiftRightArrow) => "Shift-Right",
+        Event::SpecialChar(EscapeSeq::Escape) => "Esc",
+        Event::SpecialChar(EscapeSeq::Unknown) => "Unknown",
+        Event::SpecialChar(EscapeSeq::Alt(c)) => return format!("Alt-{c}"),
+        Event::NormalChar('\r') => "Enter",
+        Event::NormalChar('\t') => "Tab",
+        Event::NormalChar('\x7f') => "Backspace",
+        Event::NormalChar(' ') => "Space",
+        Event::NormalChar('\0') => "Ctrl-Space",
+        Event::NormalChar('\x1f') => "Ctrl-/",
+        Event::NormalChar(c) if c.is_ascii_control() => {
+            let c = char::from_u32(u32::from(*c) ^ 0x40).unwrap_or(*c);
+            return format!("Ctrl-{c}");
+        }
+        Event::NormalChar(c) => return c.to_string(),
+    };
+    name.to_string()
+}
+
+/// The names of some keys, separated by spaces
+pub fn chord_name(keys: &[Event]) -> String {
+    keys.iter().map(key_name).collect::<Vec<String>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(keymap: &Keymap, mode: Mode, keys: &str) -> Option<Command> {
+        match keymap.lookup(mode, &parse_chord(keys).unwrap()) {
+            Lookup::Bound(binding) => Some(binding.command),
+            Lookup::Prefix | Lookup::Unbound => None,
+        }
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        for name in [
+            "Ctrl-S",
+            "Alt-%",
+            "Shift-Up",
+            "Esc",
+            "Enter",
+            "Ctrl-Space",
+            "x",
+            "Alt--",
+        ] {
+            assert_eq!(key_name(&parse_key(name).unwrap()), name);
+        }
+        assert_eq!(parse_key("Ctrl-s"), Some(Event::NormalChar('\x13')));
+        assert_eq!(parse_key("Ctrl-é"), None);
+        assert_eq!(parse_chord("Ctrl-X  t 2").map(|keys| keys.len()), Some(3));
+    }
+
+    #[test]
+    fn presets_and_chords() {
+        let emacs = Keymap::preset("emacs").unwrap();
+        let chord = parse_chord("Ctrl-X Ctrl-S").unwrap();
+        assert_eq!(emacs.lookup(Mode::Insert, &chord[..1]), Lookup::Prefix);
+        assert_eq!(
+            lookup(&emacs, Mode::Insert, "Ctrl-X Ctrl-S"),
+            Some(Command::Save)
+        );
+        assert_eq!(lookup(&emacs, Mode::Insert, "Ctrl-X z"), None);
+
+        let vi = Keymap::preset("vi").unwrap();
+        assert_eq!(vi.start, Mode::Normal);
+        // the table of the mode comes before the global one, including its chords
+        assert_eq!(lookup(&vi, Mode::Normal, "Ctrl-R"), Some(Command::Redo));
+        assert_eq!(
+            lookup(&vi, Mode::Insert, "Ctrl-R"),
+            Some(Command::ChooseRegister)
+        );
+        assert_eq!(lookup(&vi, Mode::Normal, "Ctrl-W"), None);
+        assert_eq!(
+            lookup(&vi, Mode::Insert, "Ctrl-W"),
+            Some(Command::SetBackup)
+        );
+        assert_eq!(lookup(&vi, Mode::Normal, "g t"), Some(Command::NextTab));
+        let keys_for = |keymap: &Keymap, mode, command| {
+            keymap.keys_for(mode, command).map(|keys| chord_name(&keys))
+        };
+        assert_eq!(
+            keys_for(&emacs, Mode::Insert, Command::Undo).as_deref(),
+            Some("Ctrl-/")
+        );
+        assert_eq!(keys_for(&vi, Mode::Normal, Command::SetBackup), None);
+        assert_eq!(
+            keys_for(&vi, Mode::Insert, Command::SetBackup).as_deref(),
+            Some("Ctrl-W")
+        );
+        assert_eq!(keys_for(&vi, Mode::Normal, Command::GotoTab), None);
+
+        let keymap: Keymap = "
+        mode = \"normal\"
+
+        [global]
+        \"Ctrl-Q\" = \"\"
+        \"Alt-1\" = \"goto-tab 3\"
+
+        [visual]
+        \"q\" = \"normal-mode\"
+        "
+        .parse()
+        .unwrap();
+        assert_eq!(keymap.start, Mode::Normal);
+        assert_eq!(lookup(&keymap, Mode::Insert, "Ctrl-Q"), None);
+        assert_eq!(lookup(&keymap, Mode::Insert, "Ctrl-S"), Some(Command::Save));
+        assert_eq!(
+            lookup(&keymap, Mode::VisualLine, "q"),
+            Some(Command::NormalMode)
+        );
+        let chord = parse_chord("Alt-1").unwrap();
+        assert_eq!(
+            keymap.lookup(Mode::Normal, &chord),
+            Lookup::Bound(&"goto-tab 3".parse().unwrap())
+        );
+        assert!(matches!(
+            "[insert]\n\"Ctrl-S\" = \"explode\"".parse::<Keymap>(),
+            Err(Error::Command(_))
+        ));
+        assert!(matches!(
+            "preset = \"nano\"".parse::<Keymap>(),
+            Err(Error::UnknownPreset(_))
+        ));
+    }
+}
@@ -0,0 +1,354 @@
+use super::buffer::Buffer;
+use super::editor::Error;
+use super::pane::{Pane, RowIter};
+use crate::frontend::ui::Colour;
+use std::mem;
+
+// the smallest share of a split, in percent, that either side can be resized down to
+const MIN_RATIO: usize = 10;
+
+/// Which way a split divides its space
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// One pane above the other
+    Rows,
+    /// One pane beside the other, with a separator between them
+    Columns,
+}
+
+/// A direction to move the focus in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Towards {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub row: usize,
+    pub col: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// How the screen is shared between panes: a tree of splits with a pane at every leaf
+#[derive(Clone, Debug, Default)]
+pub enum Layout {
+    /// Where the focused pane goes. The editor holds on to the focused pane itself, so that it
+    /// can be used alongside the buffers.
+    #[default]
+    Focused,
+    Pane(Pane),
+    Split {
+        direction: Direction,
+        /// The percentage of the space that the first side gets
+        ratio: usize,
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+/// A piece of a row of the screen
+pub enum Segment<'a> {
+    Row(RowIter<'a>),
+    Separator,
+    /// Text that isn't part of a buffer, such as a row of the file explorer
+    Text {
+        text: String,
+        background: Colour,
+    },
+}
+
+impl Layout {
+    /// Split the focused pane in two, putting a copy of it in the second half
+    pub fn split(&mut self, direction: Direction, focused: &Pane) {
+        if let Some(leaf) = self.leaves().into_iter().find(|leaf| leaf.is_focused()) {
+            *leaf = Layout::Split {
+                direction,
+                ratio: 50,
+                first: Box::new(Layout::Focused),
+                second: Box::new(Layout::Pane(focused.clone())),
+            };
+        }
+    }
+
+    fn is_focused(&self) -> bool {
+        matches!(self, Layout::Focused)
+    }
+
+    // every pane and the focused pane's place in order, from the top left to the bottom right
+    fn leaves(&mut self) -> Vec<&mut Layout> {
+        match self {
+            Layout::Split { first, second, .. } => {
+                let mut leaves = first.leaves();
+                leaves.extend(second.leaves());
+                leaves
+            }
+            leaf => vec![leaf],
+        }
+    }
+
+    /// Every pane but the focused one
+    pub fn panes(&mut self) -> impl Iterator<Item = &mut Pane> {
+        self.leaves().into_iter().filter_map(|leaf| match leaf {
+            Layout::Pane(pane) => Some(pane),
+            _ => None,
+        })
+    }
+
+    /// Close the focused pane, returning the pane that gets the focus instead, or None if it is
+    /// the only pane
+    pub fn close(&mut self) -> Option<Pane> {
+        match self {
+            Layout::Focused | Layout::Pane(_) => None,
+            Layout::Split { first, second, .. } => {
+                let sibling = if first.is_focused() {
+                    second
+                } else if second.is_focused() {
+                    first
+                } else {
+                    return first.close().or_else(|| second.close());
+                };
+                *self = mem::take(sibling.as_mut());
+                // the first pane of what is left takes over the focus
+                let leaf = self.leaves().into_iter().next()?;
+                match mem::replace(leaf, Layout::Focused) {
+                    Layout::Pane(pane) => Some(pane),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Grow (or shrink, if `by` is negative) the focused pane by a percentage of the innermost
+    /// split in a direction that it is part of
+    pub fn resize(&mut self, direction: Direction, by: isize) -> bool {
+        match self {
+            Layout::Focused | Layout::Pane(_) => false,
+            Layout::Split {
+                direction: split,
+                ratio,
+                first,
+                second,
+            } => {
+                let in_first = first.contains_focus();
+                if !in_first && !second.contains_focus() {
+                    return false;
+                }
+                let inner = if in_first { first } else { second };
+                if inner.resize(direction, by) {
+                    return true;
+                } else if *split != direction {
+                    return false;
+                }
+                let by = if in_first { by } else { -by };
+                #[allow(clippy::cast_sign_loss, clippy::cast_possible_wrap)]
+                let resized = (*ratio as isize + by).max(0) as usize;
+                *ratio = resized.clamp(MIN_RATIO, 100 - MIN_RATIO);
+                true
+            }
+        }
+    }
+
+    fn contains_focus(&self) -> bool {
+        match self {
+            Layout::Focused => true,
+            Layout::Pane(_) => false,
+            Layout::Split { first, second, .. } => {
+                first.contains_focus() || second.contains_focus()
+            }
+        }
+    }
+
+    /// Fit every pane into an area, returning where each one went in order, from the top left to
+    /// the bottom right
+    pub fn arrange(&mut self, focused: &mut Pane, area: Rect) -> Vec<Rect> {
+        match self {
+            Layout::Focused => {
+                focused.width = area.width;
+                focused.height = area.height;
+                vec![area]
+            }
+            Layout::Pane(pane) => {
+                pane.width = area.width;
+                pane.height = area.height;
+                vec![area]
+            }
+            Layout::Split {
+                direction,
+                ratio,
+                first,
+                second,
+            } => {
+                let (a, b) = match direction {
+                    Direction::Rows => {
+                        let height = area.height * *ratio / 100;
+                        (
+                            Rect { height, ..area },
+                            Rect {
+                                row: area.row + height,
+                                height: area.height - height,
+                                ..area
+                            },
+                        )
+                    }
+                    Direction::Columns => {
+                        // one column goes to the separator
+                        let width = area.width.saturating_sub(1) * *ratio / 100;
+                        (
+                            Rect { width, ..area },
+                            Rect {
+                                col: area.col + width + 1,
+                                width: area.width.saturating_sub(width + 1),
+                                ..area
+                            },
+                        )
+                    }
+                };
+                let mut rects = first.arrange(focused, a);
+                rects.extend(second.arrange(focused, b));
+                rects
+            }
+        }
+    }
+
+    /// Where the focused pane is, once the layout has been arranged in an area
+    pub fn focused_rect(&mut self, focused: &mut Pane, area: Rect) -> Rect {
+        let rects = self.arrange(focused, area);
+        let idx = self
+            .leaves()
+            .iter()
+            .position(|leaf| leaf.is_focused())
+            .unwrap_or(0);
+        rects[idx]
+    }
+
+    /// Move the focus to the nearest pane in a direction, if there is one there, swapping the
+    /// pane that had focus back into the layout. Returns whether the focus moved.
+    pub fn move_focus(&mut self, focused: &mut Pane, area: Rect, towards: Towards) -> bool {
+        let rects = self.arrange(focused, area);
+        let mut leaves = self.leaves();
+        let Some(from) = leaves.iter().position(|leaf| leaf.is_focused()) else {
+            return false;
+        };
+        let current = rects[from];
+        // how far away each rect in the direction of the move that lines up with the focused one is
+        let distance = |rect: &Rect| -> Option<usize> {
+            let overlaps_rows =
+                rect.row < current.row + current.height && current.row < rect.row + rect.height;
+            let overlaps_cols =
+                rect.col < current.col + current.width && current.col < rect.col + rect.width;
+            match towards {
+                Towards::Left if overlaps_rows && rect.col < current.col => {
+                    Some(current.col - rect.col)
+                }
+                Towards::Right if overlaps_rows && rect.col > current.col => {
+                    Some(rect.col - current.col)
+                }
+                Towards::Up if overlaps_cols && rect.row < current.row => {
+                    Some(current.row - rect.row)
+                }
+                Towards::Down if overlaps_cols && rect.row > current.row => {
+                    Some(rect.row - current.row)
+                }
+                _ => None,
+            }
+        };
+        let Some(to) = (0..rects.len())
+            .filter_map(|idx| distance(&rects[idx]).map(|distance| (distance, idx)))
+            .min()
+            .map(|(_, idx)| idx)
+        else {
+            return false;
+        };
+        if let Layout::Pane(pane) = mem::replace(&mut *leaves[to], Layout::Focused) {
+            *leaves[from] = Layout::Pane(mem::replace(focused, pane));
+        }
+        true
+    }
+
+    /// The rows of the screen that the layout covers, as the rows of each pane side by side
+    pub fn display<'a>(
+        &'a self,
+        focused: &'a Pane,
+        buffers: &'a [Buffer],
+    ) -> Result<Vec<Vec<Segment<'a>>>, Error> {
+        match self {
+            Layout::Focused => Ok(focused
+                .display(buffers, &[])?
+                .map(|row| vec![Segment::Row(row)])
+                .collect()),
+            Layout::Pane(pane) => Ok(pane
+                .display(buffers, &[])?
+                .map(|row| vec![Segment::Row(row)])
+                .collect()),
+            Layout::Split {
+                direction: Direction::Rows,
+                first,
+                second,
+                ..
+            } => {
+                let mut rows = first.display(focused, buffers)?;
+                rows.extend(second.display(focused, buffers)?);
+                Ok(rows)
+            }
+            Layout::Split {
+                direction: Direction::Columns,
+                first,
+                second,
+                ..
+            } => Ok(first
+                .display(focused, buffers)?
+                .into_iter()
+                .zip(second.display(focused, buffers)?)
+                .map(|(mut row, right)| {
+                    row.push(Segment::Separator);
+                    row.extend(right);
+                    row
+                })
+                .collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area() -> Rect {
+        Rect {
+            row: 0,
+            col: 0,
+            width: 81,
+            height: 40,
+        }
+    }
+
+    #[test]
+    fn split_and_close() {
+        let mut focused = Pane {
+            buffer_id: 1,
+            ..Pane::default()
+        };
+        let mut layout = Layout::default();
+        layout.split(Direction::Columns, &focused);
+        layout.split(Direction::Rows, &focused);
+        let rects = layout.arrange(&mut focused, area());
+        assert_eq!(rects.len(), 3);
+        assert_eq!((rects[0].width, rects[0].height), (40, 20));
+        assert_eq!((rects[2].col, rects[2].width), (41, 40));
+
+        assert!(layout.move_focus(&mut focused, area(), Towards::Right));
+        assert_eq!(layout.focused_rect(&mut focused, area()), rects[2]);
+        assert!(layout.resize(Direction::Columns, 10));
+        assert_eq!(layout.focused_rect(&mut focused, area()).width, 48);
+        assert!(!layout.resize(Direction::Rows, 10));
+
+        focused.buffer_id = 2;
+        assert_eq!(layout.close().map(|pane| pane.buffer_id), Some(1));
+        assert_eq!(layout.panes().count(), 1);
+        assert_eq!(layout.arrange(&mut focused, area())[0].height, 20);
+    }
+}
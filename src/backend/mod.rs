@@ -1,8 +1,30 @@
 pub mod buffer;
+pub mod command;
 pub mod cursor;
+pub mod dirs;
 pub mod editor;
+pub mod explorer;
+pub mod format;
+pub mod fuzzy;
+pub mod grep;
+pub mod hex;
 pub mod highlight;
+pub mod history;
+pub mod ignore;
+pub mod keymap;
 pub mod language;
+pub mod layout;
+pub mod normal;
 pub mod pane;
 pub mod prompt;
+pub mod register;
+pub mod rope;
+pub mod save;
+pub mod search;
+pub mod swap;
 pub mod syntax;
+pub mod tab;
+#[cfg(test)]
+mod testing;
+pub mod undofile;
+pub mod watch;
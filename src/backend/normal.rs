@@ -0,0 +1,577 @@
+use super::buffer::{Buffer, Line};
+use super::cursor::Cursor;
+use std::mem;
+
+// the largest count a command can be given, so that repeating it stays within reason
+const MAX_COUNT: usize = 999_999;
+
+/// Where a motion takes the cursor in Normal and Visual mode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    /// `w`: the start of the next word
+    WordStart,
+    /// `b`: the start of this word, or the one before it
+    WordBack,
+    /// `e`: the end of this word, or the one after it
+    WordEnd,
+    /// `0`
+    LineStart,
+    /// `$`
+    LineEnd,
+    /// `gg`: the first line, or the line of the count
+    FirstLine,
+    /// `G`: the last line, or the line of the count
+    LastLine,
+}
+
+impl Motion {
+    /// Whether an operator with the motion works on whole lines
+    pub fn is_linewise(self) -> bool {
+        matches!(
+            self,
+            Motion::Up | Motion::Down | Motion::FirstLine | Motion::LastLine
+        )
+    }
+
+    /// Whether an operator with the motion includes the character the motion ends on
+    pub fn is_inclusive(self) -> bool {
+        matches!(self, Motion::WordEnd | Motion::LineEnd)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+/// Where typing starts when going into Insert mode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Insert {
+    /// `i`
+    Before,
+    /// `a`
+    After,
+    /// `I`
+    LineStart,
+    /// `A`
+    LineEnd,
+    /// `o`
+    LineBelow,
+    /// `O`
+    LineAbove,
+}
+
+/// A complete Normal mode command
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Move(Motion, Option<usize>),
+    /// An operator on the text that a motion moves over
+    Operate(Operator, Motion, Option<usize>),
+    /// An operator typed twice, like `dd`, which works on the count's worth of lines
+    OperateLines(Operator, usize),
+    Insert(Insert),
+    /// `x`
+    DeleteChars(usize),
+    /// `p`, or `P` to put the text before the cursor
+    Put {
+        before: bool,
+        count: usize,
+    },
+    Undo(usize),
+    /// `v`, or `V` to select whole lines
+    Visual {
+        line: bool,
+    },
+    /// `.`
+    Repeat(usize),
+}
+
+impl Command {
+    /// Whether the command changes the text, so that `.` repeats it
+    pub fn is_change(self) -> bool {
+        match self {
+            Command::Operate(operator, _, _) | Command::OperateLines(operator, _) => {
+                operator != Operator::Yank
+            }
+            Command::Insert(_) | Command::DeleteChars(_) | Command::Put { .. } => true,
+            Command::Move(..) | Command::Undo(_) | Command::Visual { .. } | Command::Repeat(_) => {
+                false
+            }
+        }
+    }
+
+    /// Whether the command goes into Insert mode, so that what is typed there is part of it
+    pub fn is_insert(self) -> bool {
+        matches!(
+            self,
+            Command::Insert(_)
+                | Command::Operate(Operator::Change, ..)
+                | Command::OperateLines(Operator::Change, _)
+        )
+    }
+}
+
+/// What has been typed so far of a Normal mode command
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pending {
+    count: Option<usize>,
+    /// An operator waiting for its motion, with the count typed before it
+    operator: Option<(Operator, Option<usize>)>,
+    /// Whether `g` has been typed, for `gg`
+    g: bool,
+}
+
+impl Pending {
+    pub fn is_empty(&self) -> bool {
+        *self == Pending::default()
+    }
+
+    /// Add a key to the command, returning the command once it is complete. A key that can't
+    /// come next throws away what has been typed so far.
+    pub fn push(&mut self, key: char) -> Option<Command> {
+        if let (Some(digit), false) = (key.to_digit(10), self.g) {
+            // a 0 that doesn't continue a count goes to the start of the line
+            if digit != 0 || self.count.is_some() {
+                let count = self.count.unwrap_or(0);
+                let count = count.saturating_mul(10).saturating_add(digit as usize);
+                self.count = Some(count.min(MAX_COUNT));
+                return None;
+            }
+        }
+        let pending = mem::take(self);
+        let motion = match (pending.g, key) {
+            (true, 'g') => Motion::FirstLine,
+            (true, _) => return None,
+            (false, 'g') => {
+                *self = Pending { g: true, ..pending };
+                return None;
+            }
+            (false, 'h') => Motion::Left,
+            (false, 'l') => Motion::Right,
+            (false, 'k') => Motion::Up,
+            (false, 'j') => Motion::Down,
+            (false, 'w') => Motion::WordStart,
+            (false, 'b') => Motion::WordBack,
+            (false, 'e') => Motion::WordEnd,
+            (false, '0') => Motion::LineStart,
+            (false, '$') => Motion::LineEnd,
+            (false, 'G') => Motion::LastLine,
+            (false, _) => return pending.push_command(key, self),
+        };
+        Some(match pending.operator {
+            // counts before the operator and before the motion multiply, like in vi
+            Some((operator, before)) => {
+                let count = match (before, pending.count) {
+                    (None, None) => None,
+                    (before, after) => Some(
+                        before
+                            .unwrap_or(1)
+                            .saturating_mul(after.unwrap_or(1))
+                            .min(MAX_COUNT),
+                    ),
+                };
+                Command::Operate(operator, motion, count)
+            }
+            None => Command::Move(motion, pending.count),
+        })
+    }
+
+    // finish a command that isn't a motion
+    fn push_command(self, key: char, next: &mut Pending) -> Option<Command> {
+        let operator = match key {
+            'd' => Some(Operator::Delete),
+            'c' => Some(Operator::Change),
+            'y' => Some(Operator::Yank),
+            _ => None,
+        };
+        match (operator, self.operator) {
+            (Some(operator), None) => {
+                *next = Pending {
+                    operator: Some((operator, self.count)),
+                    ..Pending::default()
+                };
+                return None;
+            }
+            (Some(operator), Some((pending, before))) if operator == pending => {
+                let count = before
+                    .unwrap_or(1)
+                    .saturating_mul(self.count.unwrap_or(1))
+                    .min(MAX_COUNT);
+                return Some(Command::OperateLines(operator, count));
+            }
+            (_, Some(_)) => return None,
+            (None, None) => {}
+        }
+        let count = self.count.unwrap_or(1);
+        Some(match key {
+            'i' => Command::Insert(Insert::Before),
+            'a' => Command::Insert(Insert::After),
+            'I' => Command::Insert(Insert::LineStart),
+            'A' => Command::Insert(Insert::LineEnd),
+            'o' => Command::Insert(Insert::LineBelow),
+            'O' => Command::Insert(Insert::LineAbove),
+            'x' => Command::DeleteChars(count),
+            'p' => Command::Put {
+                before: false,
+                count,
+            },
+            'P' => Command::Put {
+                before: true,
+                count,
+            },
+            'u' => Command::Undo(count),
+            'v' => Command::Visual { line: false },
+            'V' => Command::Visual { line: true },
+            '.' => Command::Repeat(count),
+            _ => return None,
+        })
+    }
+}
+
+/// The text that an operator works on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Range {
+    /// Every line from the first row to the second, inclusive
+    Lines(usize, usize),
+    /// The text from one position up to another
+    Chars(Cursor, Cursor),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Class {
+    Space,
+    Word,
+    Punctuation,
+}
+
+fn line_len(buffer: &Buffer, row: usize) -> usize {
+    buffer.get(row).map_or(0, Line::len)
+}
+
+// the end of every line but the last counts as a space between it and the next line
+fn class_at(buffer: &Buffer, pos: Cursor) -> Class {
+    let Some(grapheme) = buffer.get(pos.row).and_then(|line| line.grapheme(pos.col)) else {
+        return Class::Space;
+    };
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => Class::Space,
+        Some(c) if c.is_alphanumeric() || c == '_' => Class::Word,
+        _ => Class::Punctuation,
+    }
+}
+
+fn is_empty_line(buffer: &Buffer, pos: Cursor) -> bool {
+    pos.col == 0 && line_len(buffer, pos.row) == 0
+}
+
+fn next(buffer: &Buffer, pos: Cursor) -> Option<Cursor> {
+    if pos.col < line_len(buffer, pos.row) {
+        Some(Cursor {
+            col: pos.col + 1,
+            ..pos
+        })
+    } else if pos.row + 1 < buffer.lines() {
+        Some(Cursor {
+            row: pos.row + 1,
+            col: 0,
+        })
+    } else {
+        None
+    }
+}
+
+fn prev(buffer: &Buffer, pos: Cursor) -> Option<Cursor> {
+    if pos.col > 0 {
+        Some(Cursor {
+            col: pos.col - 1,
+            ..pos
+        })
+    } else {
+        let row = pos.row.checked_sub(1)?;
+        Some(Cursor {
+            row,
+            col: line_len(buffer, row),
+        })
+    }
+}
+
+fn word_start(buffer: &Buffer, mut pos: Cursor) -> Cursor {
+    let class = class_at(buffer, pos);
+    // leave the word the cursor is in
+    loop {
+        match next(buffer, pos) {
+            Some(next) => pos = next,
+            None => return pos,
+        }
+        if class == Class::Space || class_at(buffer, pos) != class {
+            break;
+        }
+    }
+    // then skip the space after it, stopping at empty lines
+    while class_at(buffer, pos) == Class::Space && !is_empty_line(buffer, pos) {
+        match next(buffer, pos) {
+            Some(next) => pos = next,
+            None => return pos,
+        }
+    }
+    pos
+}
+
+fn word_end(buffer: &Buffer, mut pos: Cursor) -> Cursor {
+    match next(buffer, pos) {
+        Some(next) => pos = next,
+        None => return pos,
+    }
+    while class_at(buffer, pos) == Class::Space {
+        match next(buffer, pos) {
+            Some(next) => pos = next,
+            None => return pos,
+        }
+    }
+    let class = class_at(buffer, pos);
+    while let Some(next) = next(buffer, pos) {
+        if class_at(buffer, next) != class {
+            break;
+        }
+        pos = next;
+    }
+    pos
+}
+
+fn word_back(buffer: &Buffer, mut pos: Cursor) -> Cursor {
+    match prev(buffer, pos) {
+        Some(prev) => pos = prev,
+        None => return pos,
+    }
+    while class_at(buffer, pos) == Class::Space && !is_empty_line(buffer, pos) {
+        match prev(buffer, pos) {
+            Some(prev) => pos = prev,
+            None => return pos,
+        }
+    }
+    let class = class_at(buffer, pos);
+    if class == Class::Space {
+        return pos;
+    }
+    while let Some(prev) = prev(buffer, pos) {
+        if class_at(buffer, prev) != class {
+            break;
+        }
+        pos = prev;
+    }
+    pos
+}
+
+/// The column of the first character of a line that isn't a space
+pub fn first_non_blank(buffer: &Buffer, row: usize) -> usize {
+    let len = line_len(buffer, row);
+    (0..len)
+        .find(|col| class_at(buffer, Cursor { row, col: *col }) != Class::Space)
+        .unwrap_or(len)
+}
+
+/// Where a motion takes the cursor, repeated `count` times. The column can end up just past the
+/// end of the line, which Normal mode doesn't allow the cursor to stay at.
+pub fn target(buffer: &Buffer, from: Cursor, motion: Motion, count: Option<usize>) -> Cursor {
+    let n = count.unwrap_or(1);
+    let last_row = buffer.lines().saturating_sub(1);
+    let on_row = |row: usize| Cursor {
+        row,
+        col: from.col.min(line_len(buffer, row)),
+    };
+    let repeat =
+        |step: fn(&Buffer, Cursor) -> Cursor| (0..n).fold(from, |pos, _| step(buffer, pos));
+    match motion {
+        Motion::Left => Cursor {
+            col: from.col.saturating_sub(n),
+            ..from
+        },
+        Motion::Right => Cursor {
+            col: from.col.saturating_add(n).min(line_len(buffer, from.row)),
+            ..from
+        },
+        Motion::Up => on_row(from.row.saturating_sub(n)),
+        Motion::Down => on_row(from.row.saturating_add(n).min(last_row)),
+        Motion::WordStart => repeat(word_start),
+        Motion::WordBack => repeat(word_back),
+        Motion::WordEnd => repeat(word_end),
+        Motion::LineStart => Cursor { col: 0, ..from },
+        Motion::LineEnd => {
+            let row = from.row.saturating_add(n.saturating_sub(1)).min(last_row);
+            Cursor {
+                row,
+                col: line_len(buffer, row).saturating_sub(1),
+            }
+        }
+        Motion::FirstLine | Motion::LastLine => {
+            let row = match (motion, count) {
+                (_, Some(n)) => n.saturating_sub(1).min(last_row),
+                (Motion::FirstLine, None) => 0,
+                _ => last_row,
+            };
+            Cursor {
+                row,
+                col: first_non_blank(buffer, row),
+            }
+        }
+    }
+}
+
+/// The text that an operator with a motion works on, starting from a position
+pub fn range(
+    buffer: &Buffer,
+    from: Cursor,
+    operator: Operator,
+    motion: Motion,
+    count: Option<usize>,
+) -> Range {
+    // cw changes to the end of the word rather than up to the next one, like in vi
+    let motion = if operator == Operator::Change
+        && motion == Motion::WordStart
+        && class_at(buffer, from) != Class::Space
+    {
+        // ce from the last character of a word would go on to the next word
+        let at_end = next(buffer, from).is_none_or(|next| {
+            next.row != from.row || class_at(buffer, next) != class_at(buffer, from)
+        });
+        if at_end && count.unwrap_or(1) == 1 {
+            return Range::Chars(
+                from,
+                Cursor {
+                    col: from.col + 1,
+                    ..from
+                },
+            );
+        }
+        Motion::WordEnd
+    } else {
+        motion
+    };
+    let to = target(buffer, from, motion, count);
+    if motion.is_linewise() {
+        return Range::Lines(from.row.min(to.row), from.row.max(to.row));
+    }
+    let (start, mut end) = if to < from { (to, from) } else { (from, to) };
+    if motion.is_inclusive() {
+        end.col = (end.col + 1).min(line_len(buffer, end.row));
+    } else if end.row > start.row && end.col == 0 {
+        // a word motion onto the next line stops at the end of this one, so dw on the last word
+        // of a line doesn't join it with the next
+        end = Cursor {
+            row: end.row - 1,
+            col: line_len(buffer, end.row - 1),
+        };
+    }
+    Range::Chars(start, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::buffer;
+
+    fn at(row: usize, col: usize) -> Cursor {
+        Cursor { row, col }
+    }
+
+    #[test]
+    fn parse_commands() {
+        let mut pending = Pending::default();
+        let mut typed = |keys: &str| keys.chars().fold(None, |_, key| pending.push(key));
+        assert_eq!(typed("3w"), Some(Command::Move(Motion::WordStart, Some(3))));
+        assert_eq!(
+            typed("2d3w"),
+            Some(Command::Operate(
+                Operator::Delete,
+                Motion::WordStart,
+                Some(6)
+            ))
+        );
+        assert_eq!(typed("gg"), Some(Command::Move(Motion::FirstLine, None)));
+        assert_eq!(
+            typed("10G"),
+            Some(Command::Move(Motion::LastLine, Some(10)))
+        );
+        assert_eq!(typed("0"), Some(Command::Move(Motion::LineStart, None)));
+        assert_eq!(
+            typed("y$"),
+            Some(Command::Operate(Operator::Yank, Motion::LineEnd, None))
+        );
+        assert_eq!(
+            typed("3cc"),
+            Some(Command::OperateLines(Operator::Change, 3))
+        );
+        assert_eq!(typed("dy"), None);
+        assert_eq!(typed("4."), Some(Command::Repeat(4)));
+        assert_eq!(
+            typed("99999999999999999999p"),
+            Some(Command::Put {
+                before: false,
+                count: MAX_COUNT
+            })
+        );
+        assert_eq!(
+            typed("5000d5000w"),
+            Some(Command::Operate(
+                Operator::Delete,
+                Motion::WordStart,
+                Some(MAX_COUNT)
+            ))
+        );
+    }
+
+    #[test]
+    fn word_motions() {
+        let buffer = buffer("fn main() {\n\n    let x_1 = y;\n}");
+        let from = at(0, 0);
+        assert_eq!(target(&buffer, from, Motion::WordStart, None), at(0, 3));
+        assert_eq!(target(&buffer, from, Motion::WordStart, Some(2)), at(0, 7));
+        assert_eq!(
+            target(&buffer, at(0, 10), Motion::WordStart, None),
+            at(1, 0)
+        );
+        assert_eq!(target(&buffer, at(1, 0), Motion::WordStart, None), at(2, 4));
+        assert_eq!(target(&buffer, at(2, 8), Motion::WordEnd, None), at(2, 10));
+        assert_eq!(target(&buffer, at(2, 4), Motion::WordBack, None), at(1, 0));
+        assert_eq!(target(&buffer, at(2, 8), Motion::WordBack, None), at(2, 4));
+        assert_eq!(target(&buffer, from, Motion::LastLine, None), at(3, 0));
+        assert_eq!(
+            target(&buffer, at(3, 0), Motion::FirstLine, Some(3)),
+            at(2, 4)
+        );
+        assert_eq!(target(&buffer, from, Motion::LineEnd, None), at(0, 10));
+    }
+
+    #[test]
+    fn operator_ranges() {
+        let buffer = buffer("one two\nthree");
+        let from = at(0, 4);
+        let range = |operator, motion, count| range(&buffer, from, operator, motion, count);
+        assert_eq!(
+            range(Operator::Delete, Motion::WordStart, None),
+            Range::Chars(from, at(0, 7))
+        );
+        assert_eq!(
+            range(Operator::Change, Motion::WordStart, None),
+            Range::Chars(from, at(0, 7))
+        );
+        assert_eq!(
+            range(Operator::Delete, Motion::WordEnd, Some(2)),
+            Range::Chars(from, at(1, 5))
+        );
+        assert_eq!(
+            range(Operator::Yank, Motion::LineStart, None),
+            Range::Chars(at(0, 0), from)
+        );
+        assert_eq!(
+            range(Operator::Delete, Motion::Down, None),
+            Range::Lines(0, 1)
+        );
+    }
+}
@@ -1,8 +1,12 @@
 use super::buffer::{Buffer, Line};
-use super::cursor::{Cursor, Offset};
+use super::cursor::{Cursor, Offset, Selection};
 use super::editor::Error;
+use super::hex::{self, Hex};
 use super::highlight::{LineHighlighting, TextHighlighting};
+use super::rope::Lines;
+use super::search;
 use crate::frontend::ui::Colour;
+use regex::Regex;
 use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Clone, Debug, Default)]
@@ -11,9 +15,26 @@ pub struct Pane {
     pub width: usize,
     pub height: usize,
     pub offset: Offset,
+    /// The main cursor, which the view follows
     pub cursor: Cursor,
+    /// The end of the main selection that stays put while the cursor moves, if there is a
+    /// selection
+    pub anchor: Option<Cursor>,
+    /// Any other cursors, which edits are applied at as well as the main cursor
+    pub cursors: Vec<Selection>,
+    /// The pattern being searched for, whose matches are highlighted
+    pub search: Option<Regex>,
+    /// A message shown in the status bar until the next key press
+    pub message: Option<String>,
+    /// The editing mode shown at the start of the status bar, which only the focused pane has
+    pub indicator: Option<&'static str>,
 }
 
+// the background colour of selected text and of the extra cursors
+const SELECTION_COLOUR: Colour = Colour::Blue;
+// the background colour of search matches
+const MATCH_COLOUR: Colour = Colour::Yellow;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Char<'a> {
@@ -28,8 +49,18 @@ impl Pane {
         let buffer = buffers
             .get(self.buffer_id)
             .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        if let Some(hex) = &buffer.hex {
+            return self.move_hex_cursor(buffers, hex, dist);
+        }
         self.cursor
             .move_left_right(buffer, &mut self.offset, self.width.saturating_sub(4), dist);
+        // the view only follows the main cursor
+        for selection in &mut self.cursors {
+            selection
+                .cursor
+                .move_left_right(buffer, &mut Offset::default(), usize::MAX, dist);
+        }
+        self.merge_cursors();
         Ok(())
     }
 
@@ -37,6 +68,11 @@ impl Pane {
         let buffer = buffers
             .get(self.buffer_id)
             .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        if let Some(hex) = &buffer.hex {
+            #[allow(clippy::cast_possible_wrap)]
+            let row = (hex::BYTES_PER_ROW * 2) as isize;
+            return self.move_hex_cursor(buffers, hex, dist * row);
+        }
         debug_assert_ne!(self.height, 0, "the height of the pane cannot be 0");
         #[allow(clippy::integer_arithmetic)]
         self.cursor.move_up_down(
@@ -46,8 +82,49 @@ impl Pane {
             self.width.saturating_sub(4),
             dist,
         );
+        for selection in &mut self.cursors {
+            selection.cursor.move_up_down(
+                buffer,
+                &mut Offset::default(),
+                usize::MAX,
+                usize::MAX,
+                dist,
+            );
+        }
+        self.merge_cursors();
         Ok(())
     }
+    // move the cursor of a hex buffer by some number of nibbles
+    fn move_hex_cursor(&mut self, buffers: &[Buffer], hex: &Hex, dist: isize) -> Result<(), Error> {
+        let nibble = hex::nibble(self.cursor);
+        #[allow(clippy::cast_sign_loss)]
+        let nibble = if dist < 0 {
+            nibble.saturating_sub(-dist as usize)
+        } else {
+            nibble.saturating_add(dist as usize).min(hex.end())
+        };
+        self.cursor = hex::cursor(nibble);
+        // there are no selections in hex
+        self.anchor = None;
+        self.scroll_to_cursor(buffers)
+    }
+
+    /// Where the main cursor is drawn, relative to the top left of the pane
+    pub fn screen_cursor(&self, buffers: &[Buffer]) -> Result<(usize, usize), Error> {
+        let buffer = buffers
+            .get(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        let row = self.cursor.row - self.offset.row;
+        Ok(if buffer.hex.is_some() {
+            (row, hex::screen_col(self.cursor))
+        } else if buffer.is_norm() {
+            // leave space for the line numbers
+            (row, self.cursor.col + 4 - self.offset.col)
+        } else {
+            (row, self.cursor.col - self.offset.col)
+        })
+    }
+
     pub fn set_cursor(&mut self, row: usize, col: usize) {
         if col < self.offset.col {
             self.offset.col = col;
@@ -65,7 +142,391 @@ impl Pane {
         self.cursor.row = row;
     }
 
+    fn buffer_mut<'a>(&self, buffers: &'a mut [Buffer]) -> Result<&'a mut Buffer, Error> {
+        buffers
+            .get_mut(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))
+    }
+
+    /// The main selected range, from whichever of the anchor and the cursor comes first
+    pub fn selection(&self) -> Option<(Cursor, Cursor)> {
+        let anchor = self.anchor?;
+        Some((anchor.min(self.cursor), anchor.max(self.cursor)))
+    }
+
+    // every cursor including the main one, in the order they appear in the buffer
+    fn selections(&self) -> Vec<Selection> {
+        let mut selections = self.cursors.clone();
+        selections.push(Selection {
+            cursor: self.cursor,
+            anchor: self.anchor,
+        });
+        selections.sort_by_key(Selection::bounds);
+        selections
+    }
+
+    // combine cursors that are in the same place or whose selections overlap
+    fn merge_cursors(&mut self) {
+        if self.cursors.is_empty() {
+            return;
+        }
+        let mut merged: Vec<Selection> = Vec::new();
+        let mut main = 0;
+        for selection in self.selections() {
+            let is_main = selection.cursor == self.cursor;
+            let (start, end) = selection.bounds();
+            if let Some(last) = merged.last_mut() {
+                let (last_start, last_end) = last.bounds();
+                if start < last_end || start == last_start {
+                    if selection.anchor.is_some() || last.anchor.is_some() {
+                        *last = Selection {
+                            cursor: end.max(last_end),
+                            anchor: Some(last_start),
+                        };
+                    }
+                    if is_main {
+                        main = merged.len() - 1;
+                    }
+                    continue;
+                }
+            }
+            if is_main {
+                main = merged.len();
+            }
+            merged.push(selection);
+        }
+        let selection = merged.remove(main);
+        self.cursor = selection.cursor;
+        self.anchor = selection.anchor;
+        self.cursors = merged;
+    }
+
+    /// Add a cursor on the line below the lowest cursor, which becomes the main cursor
+    pub fn add_cursor_below(&mut self, buffers: &[Buffer]) -> Result<(), Error> {
+        let buffer = buffers
+            .get(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        let Some(last) = self.selections().iter().map(|s| s.cursor).max() else {
+            return Ok(());
+        };
+        if let Some(line) = buffer.get(last.row + 1) {
+            self.cursors.push(Selection {
+                cursor: self.cursor,
+                anchor: self.anchor,
+            });
+            self.cursor = Cursor {
+                row: last.row + 1,
+                col: last.col.min(line.len()),
+            };
+            self.anchor = None;
+        }
+        self.scroll_to_cursor(buffers)
+    }
+
+    /// Select the next occurrence of the main selection with a new main cursor, or select the
+    /// word under the cursor if nothing is selected yet
+    pub fn add_cursor_at_next_match(&mut self, buffers: &[Buffer]) -> Result<(), Error> {
+        let buffer = buffers
+            .get(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        if buffer.hex.is_some() {
+            return Ok(());
+        }
+        let Some((start, end)) = self.selection() else {
+            if let Some(line) = buffer.get(self.cursor.row) {
+                let is_word = |g: &str| g.chars().all(|c| c.is_alphanumeric() || c == '_');
+                let graphemes: Vec<&str> = line.skip(0).graphemes(true).collect();
+                let mut start = self.cursor.col;
+                while start > 0 && is_word(graphemes[start - 1]) {
+                    start -= 1;
+                }
+                let mut end = self.cursor.col;
+                while end < graphemes.len() && is_word(graphemes[end]) {
+                    end += 1;
+                }
+                if start < end {
+                    self.anchor = Some(Cursor {
+                        row: self.cursor.row,
+                        col: start,
+                    });
+                    self.cursor.col = end;
+                }
+            }
+            return self.scroll_to_cursor(buffers);
+        };
+        let needle = buffer.text_range((start.row, start.col), (end.row, end.col));
+        let after = self
+            .selections()
+            .iter()
+            .map(|s| s.bounds().1)
+            .max()
+            .unwrap_or(end);
+        let Some((from, to)) = buffer.find(&needle, (after.row, after.col)) else {
+            return Ok(());
+        };
+        let (from, to) = (
+            Cursor {
+                row: from.0,
+                col: from.1,
+            },
+            Cursor {
+                row: to.0,
+                col: to.1,
+            },
+        );
+        // every occurrence already has a cursor
+        if self.selections().iter().any(|s| s.bounds() == (from, to)) {
+            return Ok(());
+        }
+        self.cursors.push(Selection {
+            cursor: self.cursor,
+            anchor: self.anchor,
+        });
+        self.cursor = to;
+        self.anchor = Some(from);
+        self.merge_cursors();
+        self.scroll_to_cursor(buffers)
+    }
+
+    /// Go back to just the main cursor, without a selection
+    pub fn drop_cursors(&mut self) {
+        self.cursors.clear();
+        self.anchor = None;
+    }
+
+    /// Move the cursor back into the buffer after its text has been replaced from somewhere else
+    pub fn clamp_cursor(&mut self, buffers: &[Buffer]) -> Result<(), Error> {
+        self.drop_cursors();
+        self.keep_in_buffer(buffers)
+    }
+
+    /// Move every cursor and anchor that is past the end of its line or of the buffer back in,
+    /// for when the buffer has been changed through another pane
+    pub fn keep_in_buffer(&mut self, buffers: &[Buffer]) -> Result<(), Error> {
+        let buffer = buffers
+            .get(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        let clamp = |cursor: &mut Cursor| {
+            if let Some(hex) = &buffer.hex {
+                *cursor = hex::cursor(hex::nibble(*cursor).min(hex.end()));
+            } else {
+                cursor.row = cursor.row.min(buffer.lines().saturating_sub(1));
+                let len = buffer.get(cursor.row).map_or(0, Line::len);
+                cursor.col = cursor.col.min(len);
+            }
+        };
+        clamp(&mut self.cursor);
+        self.anchor.as_mut().map(clamp);
+        for selection in &mut self.cursors {
+            clamp(&mut selection.cursor);
+            selection.anchor.as_mut().map(clamp);
+        }
+        self.merge_cursors();
+        self.scroll_to_cursor(buffers)
+    }
+
+    /// Start a selection at every cursor that doesn't have one yet
+    pub fn start_selection(&mut self) {
+        self.anchor.get_or_insert(self.cursor);
+        for selection in &mut self.cursors {
+            selection.anchor.get_or_insert(selection.cursor);
+        }
+    }
+
+    /// Drop the selection of every cursor
+    pub fn clear_selection(&mut self) {
+        self.anchor = None;
+        for selection in &mut self.cursors {
+            selection.anchor = None;
+        }
+    }
+
+    /// Start a selection at the cursors, or drop the current ones
+    pub fn toggle_anchor(&mut self) {
+        if self.anchor.is_some() {
+            self.clear_selection();
+        } else {
+            self.start_selection();
+        }
+    }
+
+    /// The selected text of every cursor, one per line
+    pub fn selected_text(&self, buffers: &[Buffer]) -> Result<Option<String>, Error> {
+        let buffer = buffers
+            .get(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        if buffer.hex.is_some() {
+            return Ok(None);
+        }
+        let texts: Vec<String> = self
+            .selections()
+            .iter()
+            .filter(|s| s.anchor.is_some())
+            .map(|s| {
+                let (start, end) = s.bounds();
+                buffer.text_range((start.row, start.col), (end.row, end.col))
+            })
+            .collect();
+        Ok(if texts.is_empty() {
+            None
+        } else {
+            Some(texts.join("\n"))
+        })
+    }
+
+    /// Delete the selected text of every cursor, returning it
+    pub fn delete_selection(&mut self, buffers: &mut [Buffer]) -> Result<Option<String>, Error> {
+        let text = self.selected_text(buffers)?;
+        if text.is_some() {
+            self.replace_each(buffers, "", |_, cursor| cursor)?;
+        }
+        Ok(text)
+    }
+
+    /// Insert text at every cursor in one step, replacing the selections if there are any
+    pub fn paste(&mut self, buffers: &mut [Buffer], text: &str) -> Result<(), Error> {
+        self.replace_each(buffers, text, |_, cursor| cursor)
+    }
+
+    // replace the selection of every cursor with text, or for cursors without a selection the
+    // text from `start(cursor)` up to the cursor, as one step
+    fn replace_each(
+        &mut self,
+        buffers: &mut [Buffer],
+        text: &str,
+        start: impl Fn(&Buffer, Cursor) -> Cursor,
+    ) -> Result<(), Error> {
+        if self.is_read_only(buffers)? {
+            return Ok(());
+        } else if self.buffer_mut(buffers)?.hex.is_some() {
+            self.message = Some(String::from("only hex digits can be typed in hex"));
+            return Ok(());
+        }
+        self.record_view(buffers)?;
+        let mut selections = self.selections();
+        let main = selections
+            .iter()
+            .position(|s| s.cursor == self.cursor)
+            .unwrap_or(0);
+        let buffer = self.buffer_mut(buffers)?;
+        buffer.dirty = true;
+        buffer.begin_group();
+        if buffer.is_empty() {
+            buffer.append_string(String::new());
+        }
+        for i in 0..selections.len() {
+            let (from, to) = match selections[i].anchor {
+                Some(_) => selections[i].bounds(),
+                None => (start(buffer, selections[i].cursor), selections[i].cursor),
+            };
+            buffer.delete_range((from.row, from.col), (to.row, to.col));
+            let (row, col) = buffer.insert_text(from.row, from.col, text);
+            let end = Cursor { row, col };
+            selections[i] = Selection {
+                cursor: end,
+                anchor: None,
+            };
+            // everything after the replaced text moves along with its end
+            for later in &mut selections[i + 1..] {
+                later.cursor = later.cursor.shift(to, end);
+                later.anchor = later.anchor.map(|anchor| anchor.shift(to, end));
+            }
+        }
+        buffer.end_group();
+        self.cursor = selections.remove(main).cursor;
+        self.anchor = None;
+        self.cursors = selections;
+        self.merge_cursors();
+        self.scroll_to_cursor(buffers)?;
+        self.record_view(buffers)
+    }
+
+    // whether the buffer can't be edited, letting the user know if so
+    fn is_read_only(&mut self, buffers: &[Buffer]) -> Result<bool, Error> {
+        let buffer = buffers
+            .get(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        if buffer.read_only {
+            self.message = Some(String::from("the buffer is read only"));
+        }
+        Ok(buffer.read_only)
+    }
+
+    // let the buffer's history know where this pane is looking, so that undo can restore it
+    fn record_view(&self, buffers: &mut [Buffer]) -> Result<(), Error> {
+        buffers
+            .get_mut(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?
+            .set_view(self.cursor, self.offset);
+        Ok(())
+    }
+
+    /// Scroll the pane so that the cursor is visible, for after the cursor or the size of the
+    /// pane has changed without going through the cursor movement methods
+    pub fn scroll_to_cursor(&mut self, buffers: &[Buffer]) -> Result<(), Error> {
+        let buffer = buffers
+            .get(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        if buffer.hex.is_some() {
+            // the hex view always fits all of its columns in
+            self.offset.col = 0;
+            let rows = self.height.saturating_sub(1).max(1);
+            self.offset.row = self
+                .offset
+                .row
+                .min(self.cursor.row)
+                .max((self.cursor.row + 1).saturating_sub(rows));
+            return Ok(());
+        }
+        // normal buffers lose a row to the status bar and 4 columns to the line numbers
+        let (rows, cols) = if buffer.is_norm() {
+            (self.height.saturating_sub(1), self.width.saturating_sub(4))
+        } else {
+            (self.height, self.width)
+        };
+        if self.cursor.row < self.offset.row {
+            self.offset.row = self.cursor.row;
+        } else if self.cursor.row >= self.offset.row + rows.max(1) {
+            self.offset.row = self.cursor.row + 1 - rows.max(1);
+        }
+        if self.cursor.col < self.offset.col {
+            self.offset.col = self.cursor.col;
+        } else if self.cursor.col >= self.offset.col + cols.max(1) {
+            self.offset.col = self.cursor.col + 1 - cols.max(1);
+        }
+        Ok(())
+    }
+
     pub fn insert_grapheme(&mut self, buffers: &mut [Buffer], g: &str) -> Result<(), Error> {
+        if self.is_read_only(buffers)? {
+            return Ok(());
+        }
+        let buffer = self.buffer_mut(buffers)?;
+        if let Some(hex) = &mut buffer.hex {
+            #[allow(clippy::cast_possible_truncation)]
+            match g.chars().next().and_then(|c| c.to_digit(16)) {
+                Some(digit) if g.len() == 1 => {
+                    let nibble = hex.type_digit(hex::nibble(self.cursor), digit as u8);
+                    buffer.dirty = true;
+                    self.cursor = hex::cursor(nibble);
+                    return self.scroll_to_cursor(buffers);
+                }
+                _ => {
+                    self.message = Some(String::from("only hex digits can be typed in hex"));
+                    return Ok(());
+                }
+            }
+        }
+        if self.anchor.is_some() || !self.cursors.is_empty() {
+            // typing over a selection replaces it
+            return self.paste(buffers, if g == "\r" { "\n" } else { g });
+        }
+        self.record_view(buffers)?;
+        self.insert_grapheme_unrecorded(buffers, g)?;
+        self.record_view(buffers)
+    }
+
+    fn insert_grapheme_unrecorded(&mut self, buffers: &mut [Buffer], g: &str) -> Result<(), Error> {
         debug_assert!(self.cursor.row - self.offset.row < self.height);
         if g == "\r" {
             let buffer = buffers
@@ -107,6 +568,42 @@ impl Pane {
     }
 
     pub fn backspace(&mut self, buffers: &mut [Buffer]) -> Result<(), Error> {
+        if self.is_read_only(buffers)? {
+            return Ok(());
+        }
+        let buffer = self.buffer_mut(buffers)?;
+        if let Some(hex) = &mut buffer.hex {
+            let nibble = hex.backspace(hex::nibble(self.cursor));
+            buffer.dirty = true;
+            self.cursor = hex::cursor(nibble);
+            return self.scroll_to_cursor(buffers);
+        }
+        if self.anchor.is_some() {
+            return self.delete_selection(buffers).map(|_| ());
+        }
+        if !self.cursors.is_empty() {
+            return self.replace_each(buffers, "", |buffer, cursor| {
+                if cursor.col > 0 {
+                    Cursor {
+                        row: cursor.row,
+                        col: cursor.col - 1,
+                    }
+                } else if cursor.row > 0 {
+                    Cursor {
+                        row: cursor.row - 1,
+                        col: buffer.get(cursor.row - 1).map_or(0, Line::len),
+                    }
+                } else {
+                    cursor
+                }
+            });
+        }
+        self.record_view(buffers)?;
+        self.backspace_unrecorded(buffers)?;
+        self.record_view(buffers)
+    }
+
+    fn backspace_unrecorded(&mut self, buffers: &mut [Buffer]) -> Result<(), Error> {
         let buffer = buffers
             .get_mut(self.buffer_id)
             .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
@@ -132,6 +629,42 @@ impl Pane {
         }
     }
 
+    pub fn undo(&mut self, buffers: &mut [Buffer]) -> Result<(), Error> {
+        let buffer = buffers
+            .get_mut(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        if let Some(view) = buffer.undo() {
+            self.drop_cursors();
+            self.cursor = view.cursor;
+            self.offset = view.offset;
+        }
+        Ok(())
+    }
+
+    pub fn redo(&mut self, buffers: &mut [Buffer]) -> Result<(), Error> {
+        let buffer = buffers
+            .get_mut(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        if let Some(view) = buffer.redo() {
+            self.drop_cursors();
+            self.cursor = view.cursor;
+            self.offset = view.offset;
+        }
+        Ok(())
+    }
+
+    pub fn goto_revision(&mut self, buffers: &mut [Buffer], revision: usize) -> Result<(), Error> {
+        let buffer = buffers
+            .get_mut(self.buffer_id)
+            .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
+        if let Some(view) = buffer.goto_revision(revision) {
+            self.drop_cursors();
+            self.cursor = view.cursor;
+            self.offset = view.offset;
+        }
+        self.scroll_to_cursor(buffers)
+    }
+
     pub fn display<'a>(
         &self,
         buffers: &'a [Buffer],
@@ -142,15 +675,34 @@ impl Pane {
             .ok_or(Error::BufferClosedPrematurely(self.buffer_id))?;
 
         let status_bar = if buffer.is_norm {
-            let mut status_bar = buffer
-                .file_name
-                .as_ref()
-                .cloned()
-                .unwrap_or_else(|| String::from("[No Name]"));
+            let mut status_bar = self
+                .indicator
+                .map(|mode| format!("{mode} | "))
+                .unwrap_or_default();
+            status_bar.push_str(buffer.file_name.as_deref().unwrap_or("[No Name]"));
             status_bar.push_str(if buffer.dirty { " | + | " } else { " " });
-            status_bar.push_str(&(self.cursor.row + 1).to_string());
-            status_bar.push(':');
-            status_bar.push_str(&buffer.lines().to_string());
+            if let Some(hex) = &buffer.hex {
+                let mode = if hex.insert { "insert" } else { "overwrite" };
+                let position = format!(
+                    "byte {:#x} of {:#x} | hex {mode}",
+                    hex::nibble(self.cursor) / 2,
+                    hex.bytes.len()
+                );
+                status_bar.push_str(&position);
+            } else {
+                status_bar.push_str(&(self.cursor.row + 1).to_string());
+                status_bar.push(':');
+                status_bar.push_str(&buffer.lines().to_string());
+                status_bar.push_str(" | ");
+                status_bar.push_str(&buffer.format.to_string());
+            }
+            if buffer.read_only {
+                status_bar.push_str(" | read only");
+            }
+            if let Some(message) = &self.message {
+                status_bar.push_str(" | ");
+                status_bar.push_str(message);
+            }
             Some(status_bar)
         } else {
             None
@@ -159,11 +711,11 @@ impl Pane {
         let highlighting = buffer.highlight().unwrap_or_default();
         let iter = Iter {
             text: if buffer.is_empty() {
-                Some(default)
+                Some(Lines::from_slice(default))
             } else if buffer.lines() < self.offset.row {
                 None
             } else {
-                Some(&buffer.as_slice()[self.offset.row..])
+                Some(buffer.lines_from(self.offset.row))
             },
             col_offset: self.offset.col,
             height: self.height,
@@ -173,6 +725,25 @@ impl Pane {
             width: self.width,
             draw_tildes: buffer.is_norm(),
             highlighting,
+            selections: self
+                .selections()
+                .iter()
+                .filter_map(|s| match s.anchor {
+                    Some(_) => Some(s.bounds()),
+                    // the extra cursors are drawn as a selection of the character under them
+                    None if s.cursor != self.cursor => Some((
+                        s.cursor,
+                        Cursor {
+                            row: s.cursor.row,
+                            col: s.cursor.col + 1,
+                        },
+                    )),
+                    None => None,
+                })
+                .collect(),
+            search: self.search.clone(),
+            hex: buffer.hex.as_ref(),
+            cursor: self.cursor,
         };
         Ok(iter)
     }
@@ -180,7 +751,7 @@ impl Pane {
 
 #[derive(Debug)]
 pub struct Iter<'a> {
-    text: Option<&'a [Line]>,
+    text: Option<Lines<'a>>,
     col_offset: usize,
     width: usize,
     height: usize,
@@ -189,12 +760,20 @@ pub struct Iter<'a> {
     draw_tildes: bool,
     highlighting: TextHighlighting,
     row_offset: usize,
+    selections: Vec<(Cursor, Cursor)>,
+    search: Option<Regex>,
+    hex: Option<&'a Hex>,
+    cursor: Cursor,
 }
 
 #[derive(Debug)]
 pub enum Row<'a> {
     Normal(&'a str),
-    Empty { part_of_file: bool },
+    /// Text that isn't part of a line of the buffer, drawn without a line number
+    Owned(String),
+    Empty {
+        part_of_file: bool,
+    },
     StatusBar(String),
 }
 
@@ -206,6 +785,14 @@ pub struct RowIter<'a> {
     width: usize,
     draw_tildes: bool,
     pub highlighting: Option<LineHighlighting>,
+    col_offset: usize,
+    // the selected columns of this line, counting from the start of the line
+    selections: Vec<(usize, usize)>,
+    // the columns of this line that match the search
+    matches: Vec<(usize, usize)>,
+    background: Colour,
+    // a character held back while a colour change is drawn before it
+    pending: Option<Char<'a>>,
 }
 
 impl<'a> Iterator for Iter<'a> {
@@ -221,10 +808,59 @@ impl<'a> Iterator for Iter<'a> {
                     draw_tildes: self.draw_tildes,
                     line: self.row + self.row_offset,
                     highlighting: None,
+                    col_offset: 0,
+                    selections: Vec::new(),
+                    matches: Vec::new(),
+                    background: Colour::Reset,
+                    pending: None,
+                })
+            } else if let Some(hex) = self.hex {
+                self.row += 1;
+                let line = self.row + self.row_offset - 1;
+                let text = hex.render_row(line);
+                // show which byte the cursor is on in the ascii column too
+                let ascii = hex::ascii_col(self.cursor);
+                Some(RowIter {
+                    selections: if line == self.cursor.row && text.is_some() {
+                        vec![(ascii, ascii + 1)]
+                    } else {
+                        Vec::new()
+                    },
+                    row: text.map_or(
+                        Row::Empty {
+                            part_of_file: false,
+                        },
+                        Row::Owned,
+                    ),
+                    col: 0,
+                    width: self.width,
+                    draw_tildes: false,
+                    line: self.row + self.row_offset,
+                    highlighting: None,
+                    col_offset: 0,
+                    matches: Vec::new(),
+                    background: Colour::Reset,
+                    pending: None,
                 })
             } else {
                 self.row += 1;
-                let row = self.text.and_then(|text| text.get(self.row - 1));
+                let row = self.text.as_mut().and_then(Iterator::next);
+                let line = self.row + self.row_offset - 1;
+                let selections = self
+                    .selections
+                    .iter()
+                    .filter(|(start, end)| start.row <= line && line <= end.row)
+                    .map(|(start, end)| {
+                        let from = if line == start.row { start.col } else { 0 };
+                        // include the newline of lines that continue the selection
+                        let to = if line == end.row {
+                            end.col
+                        } else {
+                            row.map_or(0, Line::len) + 1
+                        };
+                        (from, to)
+                    })
+                    .collect();
                 Some(RowIter {
                     row: if row
                         .map(Line::len)
@@ -242,7 +878,19 @@ impl<'a> Iterator for Iter<'a> {
                     width: self.width,
                     draw_tildes: self.draw_tildes,
                     line: self.row + self.row_offset,
-                    highlighting: self.highlighting.get_line(self.row + self.row_offset - 1),
+                    highlighting: self.highlighting.get_line(line),
+                    col_offset: self.col_offset,
+                    selections: if row.is_some() {
+                        selections
+                    } else {
+                        Vec::new()
+                    },
+                    matches: match (row, &self.search) {
+                        (Some(row), Some(search)) => search::matches(row, search),
+                        _ => Vec::new(),
+                    },
+                    background: Colour::Reset,
+                    pending: None,
                 })
             }
         } else {
@@ -251,9 +899,52 @@ impl<'a> Iterator for Iter<'a> {
     }
 }
 
+impl RowIter<'_> {
+    // the background of the character that was just produced, for selections and matches
+    fn background_at(&self) -> Colour {
+        let text_start = if self.draw_tildes { 5 } else { 1 };
+        let Some(col) = self.col.checked_sub(text_start) else {
+            return Colour::Reset;
+        };
+        let col = col + self.col_offset;
+        let contains = |&(from, to): &(usize, usize)| from <= col && col < to;
+        if self.selections.iter().any(contains) {
+            SELECTION_COLOUR
+        } else if self.matches.iter().any(contains) {
+            MATCH_COLOUR
+        } else {
+            Colour::Reset
+        }
+    }
+}
+
 impl<'a> Iterator for RowIter<'a> {
     type Item = Char<'a>;
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(ch) = self.pending.take() {
+            return Some(ch);
+        }
+        let ch = match self.next_char() {
+            Some(ch) => ch,
+            None if self.background != Colour::Reset => {
+                self.background = Colour::Reset;
+                return Some(Char::Background(Colour::Reset));
+            }
+            None => return None,
+        };
+        let background = self.background_at();
+        if background == self.background {
+            Some(ch)
+        } else {
+            self.background = background;
+            self.pending = Some(ch);
+            Some(Char::Background(background))
+        }
+    }
+}
+
+impl<'a> RowIter<'a> {
+    fn next_char(&mut self) -> Option<Char<'a>> {
         match &mut self.row {
             Row::Normal(_) | Row::Empty { part_of_file: true } => {
                 if self.col < self.width {
@@ -307,6 +998,14 @@ impl<'a> Iterator for RowIter<'a> {
                     Some(Char::Normal(' '))
                 }
             }
+            Row::Owned(text) => {
+                if self.col < self.width {
+                    self.col += 1;
+                    Some(Char::Normal(text.chars().nth(self.col - 1).unwrap_or(' ')))
+                } else {
+                    None
+                }
+            }
             Row::StatusBar(sb) => {
                 if self.col == 0 {
                     self.col += 1;
@@ -324,3 +1023,66 @@ impl<'a> Iterator for RowIter<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pane() -> Pane {
+        Pane {
+            width: 80,
+            height: 24,
+            ..Pane::default()
+        }
+    }
+
+    fn text(buffer: &Buffer) -> String {
+        String::from_utf8(buffer.to_chunk()).unwrap()
+    }
+
+    #[test]
+    fn type_at_every_cursor() {
+        let mut buffers = vec![Buffer::from_bytes(b"ab\ncd\nef", None, None)];
+        let mut p = pane();
+        p.add_cursor_below(&buffers).unwrap();
+        p.add_cursor_below(&buffers).unwrap();
+        p.insert_grapheme(&mut buffers, "x").unwrap();
+        assert_eq!(text(&buffers[0]), "xab\nxcd\nxef\n");
+        p.insert_grapheme(&mut buffers, "\r").unwrap();
+        assert_eq!(text(&buffers[0]), "x\nab\nx\ncd\nx\nef\n");
+        assert_eq!(p.cursor, Cursor { row: 5, col: 0 });
+        p.backspace(&mut buffers).unwrap();
+        p.backspace(&mut buffers).unwrap();
+        assert_eq!(text(&buffers[0]), "ab\ncd\nef\n");
+        // the cursors at the start of the line get merged when there is nothing left to delete
+        p.move_cursor_up_down(&buffers, -5).unwrap();
+        assert!(p.cursors.is_empty());
+    }
+
+    #[test]
+    fn merge_overlapping_cursors() {
+        let mut buffers = vec![Buffer::from_bytes(b"abc\nabc", None, None)];
+        let mut p = pane();
+        p.cursors.push(Selection {
+            cursor: Cursor { row: 0, col: 1 },
+            anchor: None,
+        });
+        p.backspace(&mut buffers).unwrap();
+        assert_eq!(text(&buffers[0]), "bc\nabc\n");
+        assert!(p.cursors.is_empty());
+        assert_eq!(p.cursor, Cursor { row: 0, col: 0 });
+    }
+
+    #[test]
+    fn select_next_match() {
+        let mut buffers = vec![Buffer::from_bytes(b"foo bar\nfoo", None, None)];
+        let mut p = pane();
+        p.add_cursor_at_next_match(&buffers).unwrap();
+        assert_eq!(p.selected_text(&buffers).unwrap().as_deref(), Some("foo"));
+        p.add_cursor_at_next_match(&buffers).unwrap();
+        p.add_cursor_at_next_match(&buffers).unwrap();
+        assert_eq!(p.cursors.len(), 1);
+        p.paste(&mut buffers, "baz").unwrap();
+        assert_eq!(text(&buffers[0]), "baz bar\nbaz\n");
+    }
+}
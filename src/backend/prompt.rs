@@ -1,5 +1,4 @@
 use super::buffer::Buffer;
-use super::cursor::{Cursor, Offset};
 use super::editor::Error;
 
 use super::pane::{Iter, Pane};
@@ -21,10 +20,9 @@ impl Prompt {
     ) -> Result<Self, Error> {
         let mut pane = Pane {
             buffer_id,
-            cursor: Cursor::default(),
-            offset: Offset::default(),
             height: 1,
             width,
+            ..Pane::default()
         };
         for g in text[..].graphemes(true) {
             pane.insert_grapheme(buffers, g)?;
@@ -0,0 +1,59 @@
+use std::collections::{HashMap, VecDeque};
+
+// how many cut or copied pieces of text are remembered
+const KILL_RING_SIZE: usize = 10;
+
+/// Text that has been cut or copied, shared between every buffer.
+///
+/// Letters name registers that only change when they are explicitly written to, while the digits
+/// 0 to 9 read from the kill ring, with 0 being the most recently cut or copied text.
+#[derive(Clone, Debug, Default)]
+pub struct Registers {
+    named: HashMap<char, String>,
+    ring: VecDeque<String>,
+}
+
+impl Registers {
+    /// Store text in a register, or on the kill ring if no register is named
+    pub fn set(&mut self, name: Option<char>, text: String) {
+        match name {
+            Some(name) if name.is_alphabetic() => {
+                self.named.insert(name, text);
+            }
+            _ => {
+                self.ring.push_front(text);
+                self.ring.truncate(KILL_RING_SIZE);
+            }
+        }
+    }
+
+    /// Read a register, or the most recent kill if no register is named
+    pub fn get(&self, name: Option<char>) -> Option<&str> {
+        match name {
+            Some(name) => match name.to_digit(10) {
+                Some(idx) => self.ring.get(idx as usize),
+                None => self.named.get(&name),
+            },
+            None => self.ring.front(),
+        }
+        .map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kill_ring() {
+        let mut registers = Registers::default();
+        for i in 0..KILL_RING_SIZE + 2 {
+            registers.set(None, i.to_string());
+        }
+        registers.set(Some('a'), String::from("named"));
+        assert_eq!(registers.get(None), Some("11"));
+        assert_eq!(registers.get(Some('1')), Some("10"));
+        assert_eq!(registers.get(Some('a')), Some("named"));
+        assert_eq!(registers.get(Some('b')), None);
+    }
+}
@@ -0,0 +1,430 @@
+use super::buffer::Line;
+use std::fmt;
+use std::slice;
+
+// the maximum number of lines in a leaf or children in an internal node
+const MAX_CHILDREN: usize = 32;
+// nodes with fewer children than this get merged with a sibling after a removal
+const MIN_CHILDREN: usize = MAX_CHILDREN / 4;
+
+/// A rope of lines.
+///
+/// The lines are stored in the leaves of a balanced tree, and every node caches the number of
+/// lines and bytes (including a newline after every line) below it. This makes inserting,
+/// removing and editing a line, as well as converting between rows and byte offsets, O(log n) in
+/// the number of lines.
+#[derive(Clone, Default)]
+pub struct Rope {
+    root: Node,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    lines: usize,
+    bytes: usize,
+    kind: Kind,
+}
+
+#[derive(Clone, Debug)]
+enum Kind {
+    Leaf(Vec<Line>),
+    Internal(Vec<Node>),
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node::leaf(Vec::new())
+    }
+}
+
+impl Node {
+    fn leaf(lines: Vec<Line>) -> Self {
+        let mut node = Node {
+            lines: 0,
+            bytes: 0,
+            kind: Kind::Leaf(lines),
+        };
+        node.update();
+        node
+    }
+
+    fn internal(children: Vec<Node>) -> Self {
+        let mut node = Node {
+            lines: 0,
+            bytes: 0,
+            kind: Kind::Internal(children),
+        };
+        node.update();
+        node
+    }
+
+    // recompute the cached line and byte counts from the direct children
+    fn update(&mut self) {
+        let (lines, bytes) = match &self.kind {
+            Kind::Leaf(lines) => (lines.len(), lines.iter().map(|l| l.byte_len() + 1).sum()),
+            Kind::Internal(children) => children
+                .iter()
+                .fold((0, 0), |(l, b), c| (l + c.lines, b + c.bytes)),
+        };
+        self.lines = lines;
+        self.bytes = bytes;
+    }
+
+    fn children(&self) -> usize {
+        match &self.kind {
+            Kind::Leaf(lines) => lines.len(),
+            Kind::Internal(children) => children.len(),
+        }
+    }
+
+    // split off the upper half of the children into a new sibling
+    fn split(&mut self) -> Node {
+        let mut sibling = match &mut self.kind {
+            Kind::Leaf(lines) => Node::leaf(lines.split_off(lines.len() / 2)),
+            Kind::Internal(children) => Node::internal(children.split_off(children.len() / 2)),
+        };
+        self.update();
+        sibling.update();
+        sibling
+    }
+
+    // move all the children of other onto the end of self; both nodes must be at the same depth
+    fn append(&mut self, other: Node) {
+        match (&mut self.kind, other.kind) {
+            (Kind::Leaf(lines), Kind::Leaf(mut rest)) => lines.append(&mut rest),
+            (Kind::Internal(children), Kind::Internal(mut rest)) => children.append(&mut rest),
+            _ => unreachable!("the rope is unbalanced"),
+        }
+        self.update();
+    }
+
+    // find the child containing row, returning its index and the row relative to that child
+    fn locate(children: &[Node], mut row: usize, inclusive: bool) -> (usize, usize) {
+        for (i, child) in children.iter().enumerate() {
+            if row < child.lines || (inclusive && row == child.lines) {
+                return (i, row);
+            }
+            row -= child.lines;
+        }
+        (children.len().saturating_sub(1), row)
+    }
+
+    fn get(&self, row: usize) -> Option<&Line> {
+        match &self.kind {
+            Kind::Leaf(lines) => lines.get(row),
+            Kind::Internal(children) => {
+                let (i, row) = Node::locate(children, row, false);
+                children.get(i)?.get(row)
+            }
+        }
+    }
+
+    fn edit<R>(&mut self, row: usize, f: impl FnOnce(&mut Line) -> R) -> Option<R> {
+        let res = match &mut self.kind {
+            Kind::Leaf(lines) => lines.get_mut(row).map(f),
+            Kind::Internal(children) => {
+                let (i, row) = Node::locate(children, row, false);
+                children.get_mut(i)?.edit(row, f)
+            }
+        };
+        self.update();
+        res
+    }
+
+    // insert a line, returning a new sibling if this node overflowed
+    fn insert(&mut self, row: usize, line: Line) -> Option<Node> {
+        match &mut self.kind {
+            Kind::Leaf(lines) => lines.insert(row, line),
+            Kind::Internal(children) => {
+                let (i, row) = Node::locate(children, row, true);
+                if let Some(sibling) = children[i].insert(row, line) {
+                    children.insert(i + 1, sibling);
+                }
+            }
+        }
+        self.update();
+        if self.children() > MAX_CHILDREN {
+            Some(self.split())
+        } else {
+            None
+        }
+    }
+
+    fn remove(&mut self, row: usize) -> Line {
+        let line = match &mut self.kind {
+            Kind::Leaf(lines) => lines.remove(row),
+            Kind::Internal(children) => {
+                let (i, row) = Node::locate(children, row, false);
+                let line = children[i].remove(row);
+                if children[i].children() < MIN_CHILDREN && children.len() > 1 {
+                    // merge the underfull child with a neighbour, splitting them back up if the
+                    // merged node is too big
+                    let left = i.saturating_sub(1).min(children.len() - 2);
+                    let right = children.remove(left + 1);
+                    children[left].append(right);
+                    if children[left].children() > MAX_CHILDREN {
+                        let sibling = children[left].split();
+                        children.insert(left + 1, sibling);
+                    }
+                }
+                line
+            }
+        };
+        self.update();
+        line
+    }
+
+    #[cfg(test)]
+    fn offset(&self, row: usize) -> usize {
+        match &self.kind {
+            Kind::Leaf(lines) => lines.iter().take(row).map(|l| l.byte_len() + 1).sum(),
+            Kind::Internal(children) => {
+                let mut offset = 0;
+                let mut row = row;
+                for child in children {
+                    if row < child.lines {
+                        return offset + child.offset(row);
+                    }
+                    row -= child.lines;
+                    offset += child.bytes;
+                }
+                offset
+            }
+        }
+    }
+
+    // find the row containing the byte offset, along with the byte column within that row
+    fn position(&self, mut offset: usize) -> (usize, usize) {
+        match &self.kind {
+            Kind::Leaf(lines) => {
+                for (i, line) in lines.iter().enumerate() {
+                    if offset <= line.byte_len() || i + 1 == lines.len() {
+                        return (i, offset);
+                    }
+                    offset -= line.byte_len() + 1;
+                }
+                (0, offset)
+            }
+            Kind::Internal(children) => {
+                let mut row = 0;
+                for (i, child) in children.iter().enumerate() {
+                    if offset < child.bytes || i + 1 == children.len() {
+                        let (r, c) = child.position(offset);
+                        return (row + r, c);
+                    }
+                    offset -= child.bytes;
+                    row += child.lines;
+                }
+                (row, offset)
+            }
+        }
+    }
+}
+
+impl Rope {
+    pub fn lines(&self) -> usize {
+        self.root.lines
+    }
+
+    /// The total number of bytes in the rope, including a newline after every line
+    pub fn bytes(&self) -> usize {
+        self.root.bytes
+    }
+
+    pub fn get(&self, row: usize) -> Option<&Line> {
+        self.root.get(row)
+    }
+
+    /// Edit a line in place, keeping the cached byte counts up to date
+    pub fn edit<R>(&mut self, row: usize, f: impl FnOnce(&mut Line) -> R) -> Option<R> {
+        self.root.edit(row, f)
+    }
+
+    pub fn insert(&mut self, row: usize, line: Line) {
+        debug_assert!(row <= self.lines(), "inserting past the end of the rope");
+        if let Some(sibling) = self.root.insert(row, line) {
+            let old = std::mem::take(&mut self.root);
+            self.root = Node::internal(vec![old, sibling]);
+        }
+    }
+
+    pub fn remove(&mut self, row: usize) -> Line {
+        debug_assert!(row < self.lines(), "removing past the end of the rope");
+        let line = self.root.remove(row);
+        // collapse the root while it only has one child
+        while let Kind::Internal(children) = &mut self.root.kind {
+            if children.len() == 1 {
+                self.root = children.remove(0);
+            } else {
+                break;
+            }
+        }
+        line
+    }
+
+    /// The byte offset of the start of a row from the start of the rope
+    #[cfg(test)]
+    pub fn offset(&self, row: usize) -> usize {
+        self.root.offset(row)
+    }
+
+    /// Take a byte offset from the start of the rope and produce a (row, byte column) position
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        self.root.position(offset)
+    }
+
+    pub fn iter(&self) -> Lines<'_> {
+        self.iter_from(0)
+    }
+
+    /// Iterate over the lines starting at row
+    pub fn iter_from(&self, mut row: usize) -> Lines<'_> {
+        let mut stack = Vec::new();
+        let mut node = &self.root;
+        loop {
+            match &node.kind {
+                Kind::Leaf(lines) => {
+                    return Lines {
+                        stack,
+                        leaf: lines.get(row..).unwrap_or_default().iter(),
+                    };
+                }
+                Kind::Internal(children) => {
+                    let (i, r) = Node::locate(children, row, false);
+                    row = r;
+                    stack.push(children[i + 1..].iter());
+                    node = &children[i];
+                }
+            }
+        }
+    }
+}
+
+impl From<Vec<Line>> for Rope {
+    fn from(lines: Vec<Line>) -> Self {
+        let mut nodes: Vec<Node> = lines
+            .chunks(MAX_CHILDREN)
+            .map(|chunk| Node::leaf(chunk.to_vec()))
+            .collect();
+        while nodes.len() > 1 {
+            nodes = nodes
+                .chunks(MAX_CHILDREN)
+                .map(|chunk| Node::internal(chunk.to_vec()))
+                .collect();
+        }
+        Rope {
+            root: nodes.pop().unwrap_or_default(),
+        }
+    }
+}
+
+impl fmt::Debug for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An in order iterator over the lines of a rope
+#[derive(Clone, Debug)]
+pub struct Lines<'a> {
+    stack: Vec<slice::Iter<'a, Node>>,
+    leaf: slice::Iter<'a, Line>,
+}
+
+impl<'a> Lines<'a> {
+    pub fn from_slice(lines: &'a [Line]) -> Self {
+        Lines {
+            stack: Vec::new(),
+            leaf: lines.iter(),
+        }
+    }
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a Line;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.leaf.next() {
+                return Some(line);
+            }
+            let mut node = self.stack.last_mut()?.next();
+            while node.is_none() {
+                self.stack.pop();
+                node = self.stack.last_mut()?.next();
+            }
+            let mut node = node?;
+            // descend to the leftmost leaf of the next subtree
+            loop {
+                match &node.kind {
+                    Kind::Leaf(lines) => {
+                        self.leaf = lines.iter();
+                        break;
+                    }
+                    Kind::Internal(children) => {
+                        let mut iter = children.iter();
+                        let first = iter.next();
+                        self.stack.push(iter);
+                        match first {
+                            Some(first) => node = first,
+                            None => break,
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn line(i: usize) -> Line {
+        Line::new(i.to_string())
+    }
+
+    #[test]
+    fn insert_remove() {
+        let mut rope = Rope::default();
+        let mut model = Vec::new();
+        for i in 0..2000 {
+            let row = (i * 7919) % (model.len() + 1);
+            rope.insert(row, line(i));
+            model.insert(row, line(i));
+        }
+        assert_eq!(rope.lines(), model.len());
+        assert!(rope.iter().eq(model.iter()));
+        for i in 0..1500 {
+            let row = (i * 104_729) % model.len();
+            assert_eq!(rope.remove(row), model.remove(row));
+        }
+        assert!(rope.iter().eq(model.iter()));
+        for (i, l) in model.iter().enumerate() {
+            assert_eq!(rope.get(i), Some(l));
+        }
+    }
+
+    #[test]
+    fn offsets() {
+        let lines: Vec<Line> = (0..500).map(line).collect();
+        let rope = Rope::from(lines.clone());
+        let mut offset = 0;
+        for (i, l) in lines.iter().enumerate() {
+            assert_eq!(rope.offset(i), offset);
+            assert_eq!(rope.position(offset), (i, 0));
+            assert_eq!(rope.position(offset + 1), (i, 1));
+            offset += l.byte_len() + 1;
+        }
+        assert_eq!(rope.bytes(), offset);
+    }
+
+    #[test]
+    fn edit() {
+        let mut rope = Rope::from((0..100).map(line).collect::<Vec<_>>());
+        rope.edit(50, |l| l.insert_grapheme(0, "\u{2606}"));
+        assert_eq!(rope.get(50), Some(&Line::new(String::from("\u{2606}50"))));
+        assert_eq!(rope.offset(51), rope.offset(50) + "\u{2606}50\n".len());
+        assert!(rope.iter_from(98).eq([line(98), line(99)].iter()));
+    }
+}
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// how many symlinks can be followed before giving up on finding the real file
+const MAX_LINKS: usize = 40;
+
+/// Whether the old contents of a file are kept somewhere when it is saved over
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Backup {
+    #[default]
+    None,
+    /// Keep the previous version as `file~`
+    Tilde,
+    /// Keep every version as `file.<seconds since the epoch>~`
+    Timestamped,
+}
+
+impl Backup {
+    pub fn from_name(name: &str) -> Option<Backup> {
+        match name {
+            "none" => Some(Backup::None),
+            "tilde" => Some(Backup::Tilde),
+            "timestamp" => Some(Backup::Timestamped),
+            _ => None,
+        }
+    }
+
+    fn path(self, file: &Path) -> Option<PathBuf> {
+        let mut name = file.file_name()?.to_os_string();
+        match self {
+            Backup::None => return None,
+            Backup::Tilde => {}
+            Backup::Timestamped => {
+                let secs = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |time| time.as_secs());
+                name.push(format!(".{secs}"));
+            }
+        }
+        name.push("~");
+        Some(file.with_file_name(name))
+    }
+}
+
+impl fmt::Display for Backup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backup::None => write!(f, "none"),
+            Backup::Tilde => write!(f, "tilde"),
+            Backup::Timestamped => write!(f, "timestamp"),
+        }
+    }
+}
+
+/// The file that a path refers to once every symlink along the way is followed, which doesn't
+/// have to exist yet
+pub fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    for _ in 0..MAX_LINKS {
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let target = fs::read_link(&path)?;
+                // relative links are relative to the directory the link is in
+                path = match path.parent() {
+                    Some(dir) => dir.join(target),
+                    None => target,
+                };
+            }
+            _ => return Ok(path),
+        }
+    }
+    Err(io::Error::other(format!(
+        "too many levels of symlinks at {}",
+        path.display()
+    )))
+}
+
+/// Replace the contents of a file without ever leaving it half written: the new contents go into
+/// a temporary file next to it, which is synced to disk and then renamed over the original. The
+/// original's permissions and ownership are kept, and if it is a symlink the file it points to is
+/// the one that gets replaced.
+pub fn write(file_name: &str, contents: &[u8], backup: Backup) -> io::Result<()> {
+    let path = resolve(Path::new(file_name))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let existing = match fs::metadata(&path) {
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if existing.is_some() {
+        if let Some(backup) = backup.path(&path) {
+            fs::copy(&path, backup)?;
+        }
+    }
+
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".purport-{}.tmp", process::id()));
+    let temp = dir.join(temp_name);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    if let Some(metadata) = &existing {
+        options.mode(metadata.mode());
+    }
+    let mut file = options.open(&temp)?;
+    let res = (|| {
+        file.write_all(contents)?;
+        if let Some(metadata) = &existing {
+            // the mode given to open is masked by the umask, so set it again
+            file.set_permissions(metadata.permissions())?;
+            if let Err(e) =
+                std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()))
+            {
+                // only root can give files away, so saving someone else's file makes it ours
+                if e.kind() != io::ErrorKind::PermissionDenied {
+                    return Err(e);
+                }
+            }
+        }
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    res?;
+    // make sure the rename itself is on disk
+    File::open(&dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::TempDir;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn replace_through_symlink() {
+        let temp = TempDir::new("save");
+        let dir = temp.path();
+        let real = dir.join("real");
+        let link = dir.join("link");
+        fs::write(&real, "old").unwrap();
+        fs::set_permissions(&real, fs::Permissions::from_mode(0o751)).unwrap();
+        let _ = fs::remove_file(&link);
+        symlink("real", &link).unwrap();
+
+        write(link.to_str().unwrap(), b"new", Backup::Tilde).unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&real).unwrap(), b"new");
+        assert_eq!(fs::read(dir.join("real~")).unwrap(), b"old");
+        let mode = fs::metadata(&real).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 3);
+    }
+}
@@ -0,0 +1,202 @@
+use super::buffer::{Buffer, Line};
+use super::cursor::Cursor;
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// How letters of different cases match each other
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Case {
+    Sensitive,
+    Insensitive,
+    /// Insensitive unless the pattern has an uppercase letter in it
+    Smart,
+}
+
+/// The options for searching through a buffer, which are kept between searches
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Search {
+    pub case: Case,
+    pub regex: bool,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Search {
+            case: Case::Smart,
+            regex: false,
+        }
+    }
+}
+
+impl Search {
+    /// Build the regex that matches a pattern typed by the user
+    pub fn compile(self, pattern: &str) -> Result<Regex, regex::Error> {
+        let source = if self.regex {
+            pattern.to_string()
+        } else {
+            regex::escape(pattern)
+        };
+        let insensitive = match self.case {
+            Case::Sensitive => false,
+            Case::Insensitive => true,
+            Case::Smart => !pattern.chars().any(char::is_uppercase),
+        };
+        RegexBuilder::new(&source)
+            .case_insensitive(insensitive)
+            .build()
+    }
+
+    pub fn cycle_case(&mut self) {
+        self.case = match self.case {
+            Case::Smart => Case::Sensitive,
+            Case::Sensitive => Case::Insensitive,
+            Case::Insensitive => Case::Smart,
+        };
+    }
+}
+
+impl fmt::Display for Search {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let case = match self.case {
+            Case::Sensitive => "case sensitive",
+            Case::Insensitive => "ignore case",
+            Case::Smart => "smart case",
+        };
+        write!(f, "{case}{}", if self.regex { ", regex" } else { "" })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub start: Cursor,
+    pub end: Cursor,
+    /// Whether the search went past the end (or the start, going backwards) of the buffer to find
+    /// this match
+    pub wrapped: bool,
+}
+
+/// The columns that every match on a line starts and ends at, leaving out empty matches
+pub fn matches(line: &Line, regex: &Regex) -> Vec<(usize, usize)> {
+    let text = line.skip(0);
+    let mut col = 0;
+    let mut byte = 0;
+    let mut to_col = |idx: usize| {
+        col += text[byte..idx].graphemes(true).count();
+        byte = idx;
+        col
+    };
+    regex
+        .find_iter(text)
+        .filter(|m| m.start() < m.end())
+        .map(|m| (to_col(m.start()), to_col(m.end())))
+        .collect()
+}
+
+/// Find the first match starting at or after a position, or going backwards the last one starting
+/// before it, wrapping around the buffer if there isn't one
+pub fn find(buffer: &Buffer, regex: &Regex, from: Cursor, forward: bool) -> Option<Match> {
+    let rows = buffer.lines();
+    let on_row = |row: usize| {
+        buffer
+            .get(row)
+            .map(|line| matches(line, regex))
+            .unwrap_or_default()
+            .into_iter()
+            .map(move |(start, end)| Match {
+                start: Cursor { row, col: start },
+                end: Cursor { row, col: end },
+                wrapped: false,
+            })
+    };
+    if forward {
+        (from.row..rows)
+            .flat_map(on_row)
+            .find(|m| m.start >= from)
+            .or_else(|| {
+                (0..=from.row.min(rows))
+                    .flat_map(on_row)
+                    .find(|m| m.start < from)
+                    .map(|m| Match { wrapped: true, ..m })
+            })
+    } else {
+        (0..=from.row.min(rows))
+            .rev()
+            .flat_map(|row| on_row(row).rev())
+            .find(|m| m.start < from)
+            .or_else(|| {
+                (from.row..rows)
+                    .rev()
+                    .flat_map(|row| on_row(row).rev())
+                    .find(|m| m.start >= from)
+                    .map(|m| Match { wrapped: true, ..m })
+            })
+    }
+}
+
+/// The text to replace the match that starts at a column of a line with, filling in `$1`,
+/// `${name}` and so on in the template from the match's capture groups
+pub fn expand(line: &Line, regex: &Regex, col: usize, template: &str) -> String {
+    let text = line.skip(0);
+    let byte = text
+        .grapheme_indices(true)
+        .nth(col)
+        .map_or(text.len(), |(idx, _)| idx);
+    let mut expanded = String::new();
+    if let Some(captures) = regex
+        .captures_iter(text)
+        .find(|captures| captures.get(0).is_some_and(|m| m.start() == byte))
+    {
+        captures.expand(template, &mut expanded);
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::buffer;
+
+    #[test]
+    fn smart_case() {
+        let search = Search::default();
+        assert!(search.compile("foo").unwrap().is_match("FOO"));
+        assert!(!search.compile("Foo").unwrap().is_match("foo"));
+        let search = Search {
+            case: Case::Sensitive,
+            regex: true,
+        };
+        assert!(!search.compile("f.o").unwrap().is_match("FOO"));
+        assert!(search.compile("f.o").unwrap().is_match("fxo"));
+    }
+
+    #[test]
+    fn wrap_around() {
+        let b = buffer("a\u{2606}b ab\nb\nab");
+        let regex = Search::default().compile("ab").unwrap();
+        let at = |row, col| Cursor { row, col };
+        let next = find(&b, &regex, at(0, 1), true).unwrap();
+        assert_eq!(
+            (next.start, next.end, next.wrapped),
+            (at(0, 4), at(0, 6), false)
+        );
+        let next = find(&b, &regex, at(2, 1), true).unwrap();
+        assert_eq!((next.start, next.wrapped), (at(0, 4), true));
+        let prev = find(&b, &regex, at(0, 4), false).unwrap();
+        assert_eq!((prev.start, prev.wrapped), (at(2, 0), true));
+        let prev = find(&b, &regex, at(2, 0), false).unwrap();
+        assert_eq!((prev.start, prev.wrapped), (at(0, 4), false));
+    }
+
+    #[test]
+    fn capture_groups() {
+        let search = Search {
+            case: Case::Sensitive,
+            regex: true,
+        };
+        let regex = search.compile(r"(\w+)=(?P<value>\d+)").unwrap();
+        let line = Line::new(String::from("\u{2606} a=1 b=2"));
+        assert_eq!(matches(&line, &regex), vec![(2, 5), (6, 9)]);
+        assert_eq!(expand(&line, &regex, 6, "${value}=$1"), "2=b");
+    }
+}
@@ -0,0 +1,128 @@
+use super::dirs::{self, hash};
+use super::save::{self, Backup};
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// The directory that purport keeps data that shouldn't be lost but isn't worth backing up in,
+/// such as swap files
+pub fn state_dir() -> Option<PathBuf> {
+    dirs::xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+/// The swap file of an open file: the contents of its buffer as of a few seconds ago, owned by
+/// the purport that has it open
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Swap {
+    pub pid: u32,
+    pub contents: Vec<u8>,
+}
+
+impl Swap {
+    /// Whether the swap belongs to another purport that is still running
+    pub fn is_locked(&self) -> bool {
+        if self.pid == process::id() {
+            return false;
+        }
+        let Ok(pid) = libc::pid_t::try_from(self.pid) else {
+            return false;
+        };
+        // signal 0 only checks that the process exists
+        let alive = unsafe { libc::kill(pid, 0) } == 0;
+        alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+// swap files are named after the hash of the absolute path of the file they belong to, like
+// undo files
+pub fn path(file_name: &str) -> Option<PathBuf> {
+    let absolute = fs::canonicalize(file_name).unwrap_or_else(|_| PathBuf::from(file_name));
+    let mut path = state_dir()?;
+    path.push("swap");
+    path.push(hash(absolute.to_string_lossy().as_bytes()));
+    path.set_extension("swp");
+    Some(path)
+}
+
+pub fn read(path: &Path) -> Option<Swap> {
+    let bytes = fs::read(path).ok()?;
+    let newline = bytes.iter().position(|byte| *byte == b'\n')?;
+    let pid = std::str::from_utf8(&bytes[..newline])
+        .ok()?
+        .strip_prefix("pid = ")?
+        .parse()
+        .ok()?;
+    Some(Swap {
+        pid,
+        contents: bytes[newline + 1..].to_vec(),
+    })
+}
+
+/// Write the contents of a buffer to its swap file, claiming it for this purport
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut bytes = format!("pid = {}\n", process::id()).into_bytes();
+    bytes.extend_from_slice(contents);
+    let path = path
+        .to_str()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "swap path isn't unicode"))?;
+    save::write(path, &bytes, Backup::None)
+}
+
+/// Whether a swap file was written after the file it belongs to was last saved
+pub fn is_newer(path: &Path, file_name: &str) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+    match (modified(path), modified(Path::new(file_name))) {
+        (Ok(swap), Ok(file)) => swap > file,
+        (Ok(_), Err(_)) => true,
+        _ => false,
+    }
+}
+
+/// Remove a swap file, which is fine if it is already gone
+pub fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Save the contents of a buffer that would otherwise be lost, returning where they went
+pub fn recover(name: &str, contents: &[u8]) -> io::Result<PathBuf> {
+    let mut dir = state_dir().unwrap_or_else(env::temp_dir);
+    dir.push("recovery");
+    fs::create_dir_all(&dir)?;
+    let mut i = 0;
+    let path = loop {
+        let path = dir.join(format!("{name}.{}.{i}", process::id()));
+        if !path.exists() {
+            break path;
+        }
+        i += 1;
+    };
+    fs::write(&path, contents)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::TempDir;
+
+    #[test]
+    fn lock_and_read_back() {
+        let dir = TempDir::new("swap");
+        let path = dir.path().join("file.swp");
+        write(&path, b"unsaved\nchanges").unwrap();
+        let swap = read(&path).unwrap();
+        assert_eq!(swap.contents, b"unsaved\nchanges");
+        assert!(!swap.is_locked());
+        // pid 1 is always running
+        assert!(Swap { pid: 1, ..swap }.is_locked());
+    }
+}
@@ -0,0 +1,91 @@
+use super::buffer::Buffer;
+use super::layout::Layout;
+use super::pane::Pane;
+use std::iter;
+
+/// A tab page: a layout of panes of its own. The editor takes the focused pane and layout out of
+/// the open tab while it is open, so only the name of the open tab is kept here.
+#[derive(Clone, Debug, Default)]
+pub struct Tab {
+    /// The name the tab was given, if it was renamed
+    pub name: Option<String>,
+    pub pane: Pane,
+    pub layout: Layout,
+}
+
+impl Tab {
+    /// The focused pane and every other pane of the tab
+    pub fn panes(&mut self) -> impl Iterator<Item = &mut Pane> {
+        iter::once(&mut self.pane).chain(self.layout.panes())
+    }
+}
+
+/// What a tab is called in the tab bar: its name if it has one, otherwise the name of the buffer
+/// in its focused pane
+pub fn label(name: Option<&str>, focused: &Pane, buffers: &[Buffer]) -> String {
+    let buffer = buffers.get(focused.buffer_id);
+    let mut label = name
+        .or_else(|| buffer.map(Buffer::name))
+        .unwrap_or_default()
+        .to_string();
+    if buffer.is_some_and(|buffer| buffer.dirty) {
+        label.push('+');
+    }
+    label
+}
+
+/// The pieces of the tab bar, with whether each one is the open tab. When the tabs don't all fit,
+/// the ones furthest to the left are dropped until the open one does.
+pub fn bar(labels: &[String], open: usize, width: usize) -> Vec<(String, bool)> {
+    let pieces: Vec<String> = labels
+        .iter()
+        .enumerate()
+        .map(|(idx, label)| format!(" {} {} ", idx + 1, label))
+        .collect();
+    let mut start = 0;
+    while start < open
+        && pieces[start..=open]
+            .iter()
+            .map(|piece| piece.chars().count())
+            .sum::<usize>()
+            > width
+    {
+        start += 1;
+    }
+    let mut left = width;
+    let mut bar = Vec::new();
+    for (idx, piece) in pieces.into_iter().enumerate().skip(start) {
+        if left == 0 {
+            break;
+        }
+        let piece: String = piece.chars().take(left).collect();
+        left -= piece.chars().count();
+        bar.push((piece, idx == open));
+    }
+    bar
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_open_tab() {
+        let labels: Vec<String> = ["main.rs", "notes", "Cargo.toml+"]
+            .iter()
+            .map(|label| String::from(*label))
+            .collect();
+        let bar = bar(&labels, 2, 24);
+        assert_eq!(
+            bar,
+            vec![
+                (String::from(" 2 notes "), false),
+                (String::from(" 3 Cargo.toml+ "), true)
+            ]
+        );
+        assert_eq!(
+            super::bar(&labels, 0, 5),
+            vec![(String::from(" 1 ma"), true)]
+        );
+    }
+}
@@ -0,0 +1,34 @@
+//! Fixtures shared by the tests of the backend
+
+use super::buffer::Buffer;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A directory of a test's own under the temporary directory, which is removed along with
+/// everything in it when dropped, so that it goes away even when an assertion fails
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = env::temp_dir().join(format!("purport-{name}-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// A buffer holding some text, without a file or highlighting
+pub fn buffer(text: &str) -> Buffer {
+    Buffer::from_bytes(text.as_bytes(), None, None)
+}
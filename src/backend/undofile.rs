@@ -0,0 +1,76 @@
+use super::dirs::{self, hash};
+use super::history::{self, History};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// The directory that purport keeps regenerable data in, such as undo files
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::xdg_dir("XDG_CACHE_HOME", ".cache")
+}
+
+// undo files are named after the hash of the absolute path of the file they belong to
+fn undo_path(file_name: &str) -> Option<PathBuf> {
+    let absolute = fs::canonicalize(file_name).unwrap_or_else(|_| PathBuf::from(file_name));
+    let mut path = cache_dir()?;
+    path.push("undo");
+    path.push(hash(absolute.to_string_lossy().as_bytes()));
+    path.set_extension("toml");
+    Some(path)
+}
+
+/// Write the history of a file that has just been saved with the given contents
+pub fn save(file_name: &str, contents: &[u8], history: &History) -> io::Result<()> {
+    let path = undo_path(file_name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no cache directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut text = format!("hash = \"{}\"\n", hash(contents));
+    text.push_str(&history.to_toml());
+    fs::write(path, text)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Malformed(PathBuf, history::Error),
+    Remove(PathBuf, io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Malformed(path, e) => {
+                write!(f, "the undo file {} is malformed: {e}", path.display())
+            }
+            Error::Remove(path, e) => write!(
+                f,
+                "failed to remove the stale undo file {}: {e}",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Load the history of a file, as long as the file still has the contents that it had when the
+/// history was saved. Stale and malformed histories are deleted.
+pub fn load(file_name: &str, contents: &[u8]) -> Result<Option<History>, Error> {
+    let Some(path) = undo_path(file_name) else {
+        return Ok(None);
+    };
+    let Ok(text) = fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+    let expected = format!("hash = \"{}\"\n", hash(contents));
+    let parsed = text.strip_prefix(&expected).map(str::parse::<History>);
+    if let Some(Ok(history)) = parsed {
+        return Ok(Some(history));
+    }
+    let removed = fs::remove_file(&path);
+    match (parsed, removed) {
+        (Some(Err(e)), _) => Err(Error::Malformed(path, e)),
+        (_, Err(e)) => Err(Error::Remove(path, e)),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,163 @@
+use super::dirs::hash;
+use super::save;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString, OsStr};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// What a file on disk looked like the last time it was read or written, to tell whether something
+/// else has changed it since
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: String,
+}
+
+impl Stamp {
+    /// The stamp of a file that has just been read or written with the given contents
+    pub fn new(file_name: &str, contents: &[u8]) -> Stamp {
+        Stamp {
+            modified: fs::metadata(file_name)
+                .and_then(|metadata| metadata.modified())
+                .ok(),
+            len: contents.len() as u64,
+            hash: hash(contents),
+        }
+    }
+
+    /// Whether the file has different contents to when the stamp was made. Only files that look
+    /// different from the outside are read, so touching a file doesn't count as changing it.
+    pub fn changed(&self, file_name: &str) -> bool {
+        let Ok(metadata) = fs::metadata(file_name) else {
+            return true;
+        };
+        if metadata.modified().ok() == self.modified && metadata.len() == self.len {
+            return false;
+        }
+        fs::read(file_name).map_or(true, |contents| hash(&contents) != self.hash)
+    }
+}
+
+/// Watches the directories of open files with inotify. Directories are watched rather than the
+/// files themselves, so that files which are replaced by renaming over them (like purport's own
+/// saves) keep being watched.
+#[derive(Debug)]
+pub struct Watcher {
+    fd: libc::c_int,
+    dirs: HashMap<libc::c_int, PathBuf>,
+}
+
+impl Watcher {
+    pub fn new() -> io::Result<Watcher> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Watcher {
+            fd,
+            dirs: HashMap::new(),
+        })
+    }
+
+    /// Start watching the file a path refers to, following symlinks
+    pub fn watch(&mut self, file_name: &str) -> io::Result<()> {
+        let path = save::resolve(Path::new(file_name))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => fs::canonicalize(dir)?,
+            _ => fs::canonicalize(".")?,
+        };
+        if self.dirs.values().any(|watched| *watched == dir) {
+            return Ok(());
+        }
+        let c_dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE | libc::IN_DELETE;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, c_dir.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.dirs.insert(wd, dir);
+        Ok(())
+    }
+
+    /// Every file in a watched directory that has changed since the last call, without blocking
+    pub fn changed(&mut self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        // big enough for plenty of events with the longest names
+        let mut buf = [0_u8; 16 * 1024];
+        loop {
+            let read = unsafe { libc::read(self.fd, buf.as_mut_ptr().cast(), buf.len()) };
+            let Ok(read) = usize::try_from(read) else {
+                // either there are no more events, or something went wrong and we'll try again
+                // next time
+                break;
+            };
+            let mut idx = 0;
+            while idx + mem::size_of::<libc::inotify_event>() <= read {
+                let event: libc::inotify_event = unsafe {
+                    buf.as_ptr()
+                        .add(idx)
+                        .cast::<libc::inotify_event>()
+                        .read_unaligned()
+                };
+                let name_start = idx + mem::size_of::<libc::inotify_event>();
+                let name_end = name_start + event.len as usize;
+                if let (Some(dir), Some(name)) = (
+                    self.dirs.get(&event.wd),
+                    buf.get(name_start..name_end)
+                        .and_then(|name| CStr::from_bytes_until_nul(name).ok()),
+                ) {
+                    let path = dir.join(OsStr::from_bytes(name.to_bytes()));
+                    if !paths.contains(&path) {
+                        paths.push(path);
+                    }
+                }
+                idx = name_end;
+            }
+        }
+        paths
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Whether a path names the same file as another one that has already been resolved
+pub fn is_file(file_name: &str, resolved: &Path) -> bool {
+    save::resolve(Path::new(file_name))
+        .and_then(fs::canonicalize)
+        .is_ok_and(|path| path == resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::testing::TempDir;
+
+    #[test]
+    fn notice_changes() {
+        let dir = TempDir::new("watch");
+        let file = dir.path().join("file");
+        let file_name = file.to_str().unwrap();
+        fs::write(&file, "old").unwrap();
+        let stamp = Stamp::new(file_name, b"old");
+        let mut watcher = Watcher::new().unwrap();
+        watcher.watch(file_name).unwrap();
+        assert!(!stamp.changed(file_name));
+
+        save::write(file_name, b"newer", save::Backup::None).unwrap();
+        assert!(stamp.changed(file_name));
+        let changed = watcher.changed();
+        assert!(changed.iter().any(|path| is_file(file_name, path)));
+        assert!(watcher.changed().is_empty());
+    }
+}
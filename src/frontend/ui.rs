@@ -1,6 +1,7 @@
 use std::io;
 use std::process::ExitStatus;
 use std::str;
+use std::time::Duration;
 
 pub trait UI {
     fn draw(&mut self, text: &str);
@@ -8,29 +9,51 @@ pub trait UI {
     fn set_foreground(&mut self, colour: Colour);
     fn set_background(&mut self, colour: Colour);
     fn next_event(&mut self) -> Result<Event, Error>;
+    /// Wait for the next event for at most `timeout`
+    fn poll_event(&mut self, _timeout: Duration) -> Result<Option<Event>, Error> {
+        self.next_event().map(Some)
+    }
     fn width(&self) -> usize;
     fn height(&self) -> usize;
     fn refresh(&mut self) -> Result<(), Error>;
     fn move_cursor(&mut self, row: usize, col: usize);
+    /// Change how the cursor looks, for UIs that can
+    fn set_cursor_shape(&mut self, _shape: CursorShape) {}
     fn drawln(&mut self, text: &str) {
         self.draw(text);
         self.newln();
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Event {
     NormalChar(char),
     SpecialChar(EscapeSeq),
 }
 
 #[allow(clippy::enum_variant_names)]
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
 pub enum EscapeSeq {
     LeftArrow,
     RightArrow,
     UpArrow,
     DownArrow,
+    ShiftLeftArrow,
+    ShiftRightArrow,
+    ShiftUpArrow,
+    ShiftDownArrow,
+    /// A key pressed while holding alt
+    Alt(char),
+    /// The escape key on its own
+    Escape,
+    Unknown,
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Bar,
+    Underline,
 }
 
 #[allow(dead_code)]
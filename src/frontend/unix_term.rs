@@ -1,7 +1,18 @@
-use super::ui::{Colour, Error, EscapeSeq, Event, UI};
-use std::io::{self, Read, Write};
+use super::ui::{Colour, CursorShape, Error, EscapeSeq, Event, UI};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{self, Write};
 use std::ops;
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// whether the terminal has been put back the way it was, which only needs doing once even if
+// both the panic hook and dropping the term try to
+static CLEANED_UP: AtomicBool = AtomicBool::new(false);
+// how long to wait for the rest of an escape sequence before deciding that escape was pressed on
+// its own
+const ESCAPE_TIMEOUT: Duration = Duration::from_millis(25);
 
 #[derive(Clone, Debug)]
 pub struct Term {
@@ -11,6 +22,9 @@ pub struct Term {
     row: usize,
     cursor_row: usize,
     cursor_col: usize,
+    cursor_shape: CursorShape,
+    // bytes that have been read from stdin but not turned into events yet
+    input: VecDeque<u8>,
 }
 /* for every line to be printed:
 collect all the data
@@ -45,28 +59,53 @@ impl UI for Term {
         );
     }
     fn next_event(&mut self) -> Result<Event, Error> {
-        let c = io::stdin()
-            .bytes()
-            .nth(0)
-            .ok_or(Error::FailedStdinRead)?
-            .map_err(Error::IOErr)?;
+        let c = self.next_byte()?;
         if c == b'\x1b' {
+            if !self.wait_for_input(ESCAPE_TIMEOUT)? {
+                return Ok(Event::SpecialChar(EscapeSeq::Escape));
+            }
             let mut esc = String::new();
-            for byte in io::stdin().bytes() {
-                esc.push(byte.map(|b| b as char).map_err(Error::IOErr)?);
+            loop {
+                esc.push(self.next_byte()? as char);
                 match esc.as_str() {
-                    "[A" => return Ok(Event::SpecialChar(EscapeSeq::UpArrow)),
-                    "[B" => return Ok(Event::SpecialChar(EscapeSeq::DownArrow)),
-                    "[C" => return Ok(Event::SpecialChar(EscapeSeq::RightArrow)),
-                    "[D" => return Ok(Event::SpecialChar(EscapeSeq::LeftArrow)),
+                    "[A" | "OA" => return Ok(Event::SpecialChar(EscapeSeq::UpArrow)),
+                    "[B" | "OB" => return Ok(Event::SpecialChar(EscapeSeq::DownArrow)),
+                    "[C" | "OC" => return Ok(Event::SpecialChar(EscapeSeq::RightArrow)),
+                    "[D" | "OD" => return Ok(Event::SpecialChar(EscapeSeq::LeftArrow)),
+                    "[1;2A" => return Ok(Event::SpecialChar(EscapeSeq::ShiftUpArrow)),
+                    "[1;2B" => return Ok(Event::SpecialChar(EscapeSeq::ShiftDownArrow)),
+                    "[1;2C" => return Ok(Event::SpecialChar(EscapeSeq::ShiftRightArrow)),
+                    "[1;2D" => return Ok(Event::SpecialChar(EscapeSeq::ShiftLeftArrow)),
+                    "[" | "O" => (),
+                    // anything else is either alt and a key, or a control sequence that ended
+                    // with a letter or a tilde
+                    e if e.len() == 1 && e.chars().all(|c| c.is_ascii_graphic()) => {
+                        return Ok(Event::SpecialChar(EscapeSeq::Alt(
+                            esc.as_bytes()[0] as char,
+                        )))
+                    }
+                    e if e.len() == 1
+                        || e.ends_with(|c: char| c.is_ascii_alphabetic() || c == '~') =>
+                    {
+                        return Ok(Event::SpecialChar(EscapeSeq::Unknown))
+                    }
                     _ => (),
                 }
             }
-            Err(Error::FailedStdinRead)
         } else {
             Ok(Event::NormalChar(c as char))
         }
     }
+    fn poll_event(&mut self, timeout: Duration) -> Result<Option<Event>, Error> {
+        if self.wait_for_input(timeout)? {
+            self.next_event().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+    fn set_cursor_shape(&mut self, shape: CursorShape) {
+        self.cursor_shape = shape;
+    }
     fn set_foreground(&mut self, colour: Colour) {
         #[allow(clippy::indexing_slicing)]
         self.buffer[self.row].push_str(match colour {
@@ -110,14 +149,72 @@ impl UI for Term {
             );
             *line = String::new();
         }
-        print!("\x1b[?25h\x1b[{};{}H", self.cursor_row, self.cursor_col);
+        let shape = match self.cursor_shape {
+            CursorShape::Block => 2,
+            CursorShape::Underline => 4,
+            CursorShape::Bar => 6,
+        };
+        print!(
+            "\x1b[{shape} q\x1b[?25h\x1b[{};{}H",
+            self.cursor_row, self.cursor_col
+        );
         io::stdout().flush().map_err(Error::IOErr)
     }
 }
 
 impl Term {
-    fn cleanup() -> io::Result<()> {
-        print!("\x1b[?25h\x1b[2J\x1b[;H");
+    // wait for at most `timeout` for there to be input, returning whether there is any
+    fn wait_for_input(&self, timeout: Duration) -> Result<bool, Error> {
+        if !self.input.is_empty() {
+            return Ok(true);
+        }
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+        let ready = unsafe { libc::poll(&raw mut fd, 1, timeout) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            // a signal such as a resize interrupting the wait isn't an error
+            return if e.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(Error::IOErr(e))
+            };
+        }
+        Ok(ready > 0)
+    }
+
+    // read straight from the file descriptor, so that no input is hidden from poll in the buffer
+    // of io::stdin
+    fn next_byte(&mut self) -> Result<u8, Error> {
+        while self.input.is_empty() {
+            let mut buf = [0; 1024];
+            let read =
+                unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+            match usize::try_from(read) {
+                Ok(0) => return Err(Error::FailedStdinRead),
+                Ok(read) => self.input.extend(&buf[..read]),
+                Err(_) => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() != io::ErrorKind::Interrupted {
+                        return Err(Error::IOErr(e));
+                    }
+                }
+            }
+        }
+        self.input.pop_front().ok_or(Error::FailedStdinRead)
+    }
+
+    /// Take the terminal out of raw mode and clear the screen
+    pub fn cleanup() -> io::Result<()> {
+        if CLEANED_UP.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        // the cursor goes back to the shape the terminal gives it by default
+        print!("\x1b[0 q\x1b[?25h\x1b[2J\x1b[;H");
         io::stdout().flush()?;
 
         Command::new("stty")
@@ -197,7 +294,9 @@ impl Term {
             buffer: vec![String::new(); height],
             cursor_col: 0,
             cursor_row: 0,
+            cursor_shape: CursorShape::Block,
             row: 0,
+            input: VecDeque::new(),
         })
     }
 }
@@ -14,13 +14,32 @@ mod frontend;
 use backend::editor::{Editor, Error};
 use frontend::unix_term::Term;
 use std::env::args;
+use std::panic;
 
 fn main() -> Result<(), Error> {
+    // the editor saves its unsaved buffers as the panic unwinds through it, but the terminal has
+    // to be usable to read about it
+    panic::set_hook(Box::new(|info| {
+        if let Err(e) = Term::cleanup() {
+            eprintln!("failed to cleanup term: {e:?}");
+        }
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("no message");
+        match info.location() {
+            Some(location) => eprintln!("purport panicked at {location}: {message}"),
+            None => eprintln!("purport panicked: {message}"),
+        }
+    }));
     let term = Term::sys_default().map_err(Error::UI)?;
     let mut ed = Editor::open(term)?;
 
-    let fp = args().nth(1);
-    ed.load_into(1, fp);
+    for fp in args().skip(1) {
+        ed.open_file(fp)?;
+    }
     ed.mainloop()?;
 
     println!("thanks for editing with purport!");